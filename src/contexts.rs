@@ -1,9 +1,11 @@
 
 
 pub mod bizhawk;
+pub mod custom;
 pub mod fceux;
 pub mod gens;
 
 pub use bizhawk::BizHawkContext;
+pub use custom::CustomContext;
 pub use fceux::FceuxContext;
 pub use gens::GensContext;
\ No newline at end of file
@@ -1,14 +1,58 @@
 use camino::Utf8PathBuf;
-use crate::{EmulatorContext, Error};
-use crate::includes::{BIZHAWK_BASH_DEFAULT, BIZHAWK_BASH_PRE290, copy_if_different};
+use crate::{CommonOptions, EmulatorContext, Error};
+use crate::doctor::Requirement;
+use crate::env::{Deterministic, EnvInheritance};
+use crate::includes::{BIZHAWK_BASH_DEFAULT, BIZHAWK_BASH_PRE290, create_temp_dir};
+use crate::manifest::Manifest;
+use crate::placement::{require_file, PlacedFiles, Placements};
+
+pub mod config;
+
+use config::{BizHawkConfig, BizHawkSetting};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct BizHawkContext {
     pub config: Option<Utf8PathBuf>,
+    
+    /// Settings applied on top of the base config, written to a per-run config during `prepare`.
+    /// 
+    /// The base config is [`config`](BizHawkContext::config) if set, otherwise `config.ini` in the working directory, if it exists.
+    pub config_overrides: Vec<BizHawkSetting>,
     pub movie: Option<Utf8PathBuf>,
     pub lua: Option<Utf8PathBuf>,
     pub rom: Option<Utf8PathBuf>,
+    
+    /// How the ROM, movie and Lua script are made available to the emulator.
+    pub placement: Placements,
+    
+    /// Whether multiple instances may run from the working directory at once.
+    /// 
+    /// When true, [`run`](crate::run) holds a [shared](crate::lock::LockMode::Shared) lock on the working directory for the whole run,
+    /// instead of an exclusive one. `prepare` and `cleanup` still happen one run at a time, under a separate prepare lock.
+    pub concurrent_instances: bool,
+    
+    /// If set, the emulator is run with a [`Deterministic`] environment. Unless [`env_inheritance`](Self::env_inheritance)
+    /// is also set, only [essential](EnvInheritance::essential) variables are then inherited from the parent environment.
+    pub deterministic: Option<Deterministic>,
+    
+    /// Which variables are inherited from the parent environment.
+    /// 
+    /// If not set, only [essential](EnvInheritance::essential) variables are inherited when [`deterministic`](Self::deterministic)
+    /// is set, otherwise everything is inherited.
+    pub env_inheritance: Option<EnvInheritance>,
+    
+    /// Additional environment variables, applied after any set by emu-runner.
+    pub extra_env: Vec<(String, String)>,
+    
+    /// Additional arguments passed to the emulator before the ROM.
+    pub extra_args: Vec<String>,
+    
+    /// Additional arguments passed to the emulator after the ROM.
+    pub trailing_args: Vec<String>,
     pub working_dir: Utf8PathBuf,
+    temp_dir: Option<Utf8PathBuf>,
+    placed: PlacedFiles,
+    manifest: Manifest,
 }
 impl EmulatorContext for BizHawkContext {
     fn cmd_name(&self) -> String {
@@ -27,64 +71,66 @@ impl EmulatorContext for BizHawkContext {
             args.push("start-bizhawk.sh".into());
         }
         
-        if let Some(config) = self.config.as_ref() {
+        if let Some(config) = self.config_path() {
             args.push(format!("--config={config}"));
         }
-        if let Some(movie) = self.movie.as_ref() {
+        if let Some(movie) = self.placed.movie.as_ref().or(self.movie.as_ref()) {
             args.push(format!("--movie={movie}"));
         }
-        if let Some(lua) = self.lua.as_ref() {
+        if let Some(lua) = self.placed.lua.as_ref().or(self.lua.as_ref()) {
             args.push(format!("--lua={lua}"));
         }
-        if let Some(rom) = self.rom.as_ref() {
+        args.extend(self.extra_args.iter().cloned());
+        if let Some(rom) = self.placed.rom.as_ref().or(self.rom.as_ref()) {
             args.push(rom.to_string());
         }
+        args.extend(self.trailing_args.iter().cloned());
         
         args
     }
     
     fn env(&self) -> Vec<(String, String)> {
-        vec![]
+        let mut vars = match self.deterministic.as_ref() {
+            Some(deterministic) => deterministic.vars(),
+            None => vec![],
+        };
+        vars.extend(self.extra_env.iter().cloned());
+        
+        vars
     }
 
     fn prepare(&mut self) -> Result<(), Error> {
         // BizHawk accepts configs/movies/scripts/roms from anywhere,
-        // so we only need to verify they exist.
+        // so we only need to verify they exist, then place them according to the policy.
         // However, since we change the working directory, and there's no
         // easy way to test if file exists relative to a different dir,
         // the paths _should_ be absolute, either originally or via the with_* functions.
         
         if let Some(config) = self.config.as_ref() {
-            if !config.is_file() {
-                return Err(Error::MissingConfig(config.clone()));
-            }
+            require_file(config, Error::MissingConfig)?;
             if !config.is_absolute() {
                 return Err(Error::AbsolutePathFailed);
             }
         }
-        if let Some(movie) = self.movie.as_ref() {
-            if !movie.is_file() {
-                return Err(Error::MissingMovie(movie.clone()));
-            }
-            if !movie.is_absolute() {
-                return Err(Error::AbsolutePathFailed);
-            }
-        }
-        if let Some(lua) = self.lua.as_ref() {
-            if !lua.is_file() {
-                return Err(Error::MissingLua(lua.clone()));
-            }
-            if !lua.is_absolute() {
-                return Err(Error::AbsolutePathFailed);
-            }
-        }
-        if let Some(rom) = self.rom.as_ref() {
-            if !rom.is_file() {
-                return Err(Error::MissingRom(rom.clone()));
-            }
-            if !rom.is_absolute() {
-                return Err(Error::AbsolutePathFailed);
+        self.placed = self.placement.place_inputs(self.rom.as_ref(), self.movie.as_ref(), self.lua.as_ref(), &self.working_dir, &mut self.manifest)?;
+        
+        if !self.config_overrides.is_empty() {
+            let base = self.config.clone().unwrap_or_else(|| self.working_dir.join("config.ini"));
+            let mut config = if base.is_file() {
+                BizHawkConfig::load(base)?
+            } else {
+                BizHawkConfig::new()
+            };
+            for setting in &self.config_overrides {
+                config.apply(setting)?;
             }
+            
+            let dir = match self.temp_dir.as_ref() {
+                Some(dir) => dir.clone(),
+                None => create_temp_dir("emu-runner-bizhawk")?,
+            };
+            config.save(dir.join("config.ini"))?;
+            self.temp_dir = Some(dir);
         }
         
         // If unix, copy bash script and check for incompatible versions
@@ -105,15 +151,71 @@ impl EmulatorContext for BizHawkContext {
             
             let mut path = self.working_dir.clone();
             path.push("start-bizhawk.sh");
-            copy_if_different(bash, path)?;
+            self.manifest.write(bash, path)?;
         }
         
         Ok(())
     }
 
+    fn cleanup(&mut self) -> Result<(), Error> {
+        self.manifest.restore()?;
+        self.placed = PlacedFiles::default();
+        
+        if let Some(dir) = self.temp_dir.take() {
+            std::fs::remove_dir_all(dir)?;
+        }
+        
+        Ok(())
+    }
+    
     fn working_dir(&self) -> Utf8PathBuf {
         self.working_dir.clone()
     }
+    
+    fn set_working_dir(&mut self, working_dir: Utf8PathBuf) -> Result<(), Error> {
+        self.working_dir = working_dir;
+        
+        Ok(())
+    }
+    
+    fn supports_concurrent_instances(&self) -> bool {
+        self.concurrent_instances
+    }
+    
+    fn env_inheritance(&self) -> EnvInheritance {
+        match (self.env_inheritance.as_ref(), self.deterministic.as_ref()) {
+            (Some(inheritance), _) => inheritance.clone(),
+            (None, Some(_)) => EnvInheritance::essential(),
+            (None, None) => EnvInheritance::All,
+        }
+    }
+    
+    fn requirements(&self) -> Vec<Requirement> {
+        let executable = Requirement::Executable(self.working_dir.join("EmuHawk.exe"));
+        
+        #[cfg(target_family = "unix")]
+        { vec![Requirement::Bash, Requirement::Mono, executable] }
+        
+        #[cfg(target_family = "windows")]
+        { vec![executable] }
+    }
+}
+impl CommonOptions for BizHawkContext {
+    fn set_rom(&mut self, rom: Option<Utf8PathBuf>) {
+        self.rom = rom;
+    }
+    
+    fn set_movie(&mut self, movie: Option<Utf8PathBuf>) {
+        self.movie = movie;
+    }
+    
+    fn set_lua(&mut self, lua: Option<Utf8PathBuf>) {
+        self.lua = lua;
+    }
+    
+    fn set_config(&mut self, config: Option<Utf8PathBuf>) {
+        self.config = config;
+    }
 }
 impl BizHawkContext {
     /// Creates a new Context with default options.
@@ -136,45 +238,121 @@ impl BizHawkContext {
         
         Ok(Self {
             config: None,
+            config_overrides: vec![],
             movie: None,
             lua: None,
             rom: None,
+            placement: Placements::default(),
+            concurrent_instances: false,
+            deterministic: None,
+            env_inheritance: None,
+            extra_env: vec![],
+            extra_args: vec![],
+            trailing_args: vec![],
             working_dir,
+            temp_dir: None,
+            placed: PlacedFiles::default(),
+            manifest: Manifest::new(),
         })
     }
     
-    pub fn with_config<P: Into<Utf8PathBuf>>(self, config: P) -> Self {
-        let config = config.into();
+    /// Adds a setting to apply on top of the base config.
+    pub fn with_config_override(mut self, setting: BizHawkSetting) -> Self {
+        self.config_overrides.push(setting);
+        self
+    }
+    
+    /// Sets whether multiple instances may run from the working directory at once.
+    pub fn with_concurrent_instances(self, concurrent_instances: bool) -> Self {
         Self {
-            config: Some(config.canonicalize_utf8().unwrap_or_else(|_| config)),
+            concurrent_instances,
             ..self
         }
     }
     
-    pub fn with_movie<P: Into<Utf8PathBuf>>(self, movie: P) -> Self {
-        let movie = movie.into();
+    /// Runs the emulator with the given environment preset, to make runs reproducible across machines.
+    pub fn with_deterministic(self, deterministic: Deterministic) -> Self {
         Self {
-            movie: Some(movie.canonicalize_utf8().unwrap_or_else(|_| movie)),
+            deterministic: Some(deterministic),
             ..self
         }
     }
     
-    pub fn with_lua<P: Into<Utf8PathBuf>>(self, lua: P) -> Self {
-        let lua = lua.into();
+    pub fn with_env_inheritance(self, env_inheritance: EnvInheritance) -> Self {
         Self {
-            lua: Some(lua.canonicalize_utf8().unwrap_or_else(|_| lua)),
+            env_inheritance: Some(env_inheritance),
             ..self
         }
     }
     
+    /// Adds an environment variable, overriding any set by emu-runner.
+    pub fn with_env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.extra_env.push((key.into(), value.into()));
+        self
+    }
+    
+    /// Adds an argument passed to the emulator before the ROM.
+    pub fn with_extra_arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.extra_args.push(arg.into());
+        self
+    }
+    
+    /// Adds an argument passed to the emulator after the ROM.
+    pub fn with_trailing_arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.trailing_args.push(arg.into());
+        self
+    }
+    
+    /// Same as [`CommonOptions::with_rom`], usable without importing the trait.
     pub fn with_rom<P: Into<Utf8PathBuf>>(self, rom: P) -> Self {
-        let rom = rom.into();
+        CommonOptions::with_rom(self, rom)
+    }
+    
+    /// Same as [`CommonOptions::with_movie`], usable without importing the trait.
+    pub fn with_movie<P: Into<Utf8PathBuf>>(self, movie: P) -> Self {
+        CommonOptions::with_movie(self, movie)
+    }
+    
+    /// Same as [`CommonOptions::with_lua`], usable without importing the trait.
+    pub fn with_lua<P: Into<Utf8PathBuf>>(self, lua: P) -> Self {
+        CommonOptions::with_lua(self, lua)
+    }
+    
+    /// Same as [`CommonOptions::with_config`], usable without importing the trait.
+    pub fn with_config<P: Into<Utf8PathBuf>>(self, config: P) -> Self {
+        CommonOptions::with_config(self, config)
+    }
+    
+    /// Sets how the ROM, movie and Lua script are made available to the emulator.
+    pub fn with_placement(self, placement: Placements) -> Self {
         Self {
-            rom: Some(rom.canonicalize_utf8().unwrap_or_else(|_| rom)),
+            placement,
             ..self
         }
     }
     
+    /// Returns the config file passed to BizHawk.
+    /// 
+    /// If there are config overrides, this is the per-run config, which only exists once the context has been prepared.
+    pub fn config_path(&self) -> Option<Utf8PathBuf> {
+        match self.temp_dir.as_ref() {
+            Some(dir) if !self.config_overrides.is_empty() => Some(dir.join("config.ini")),
+            _ => self.config.clone(),
+        }
+    }
+    
+    /// Returns the files written into the emulator's directory by `prepare`, which are restored during `cleanup`.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+    
+    /// Returns the paths of the ROM, movie and Lua script passed to the emulator by the current run.
+    /// 
+    /// Empty until the context has been prepared.
+    pub fn placed(&self) -> &PlacedFiles {
+        &self.placed
+    }
+    
     /// Determines the emulator version by comparing the SHA1 checksum of `EmuHawk.exe`
     pub fn detect_version(&self) -> Option<String> {
         let mut exe = self.working_dir.clone();
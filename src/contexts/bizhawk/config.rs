@@ -0,0 +1,136 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use serde_json::{Map, Value};
+use crate::Error;
+
+/// A single typed change to BizHawk's `config.ini`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BizHawkSetting {
+    /// Core used for a system (e.g. system `NES`, core `NesHawk`).
+    PreferredCore { system: String, core: String },
+    /// Enables or disables the clock throttle.
+    Throttle(bool),
+    /// Emulation speed, as a percentage of normal speed.
+    SpeedPercent(u32),
+    /// Enables or disables sound output.
+    Sound(bool),
+    /// Directory firmware files are loaded from.
+    FirmwarePath(Utf8PathBuf),
+    /// Directory movies are saved to.
+    MoviePath(Utf8PathBuf),
+    /// Directory savestates for a system are saved to.
+    SavestatePath { system: String, path: Utf8PathBuf },
+    /// Window zoom factor for a system.
+    WindowScale { system: String, scale: u32 },
+    /// Any other top-level key, written as-is.
+    Raw { key: String, value: Value },
+}
+
+/// BizHawk's JSON `config.ini`, which can be edited setting by setting.
+/// 
+/// Fields that aren't modified, including ones this type doesn't know about, are kept unchanged and in order.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BizHawkConfig {
+    root: Map<String, Value>,
+}
+impl BizHawkConfig {
+    /// Creates an empty config. BizHawk fills in defaults for every missing field.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        match serde_json::from_str(text)? {
+            Value::Object(root) => Ok(Self { root }),
+            _ => Err(Error::UnsupportedSetting("config root is not an object".into())),
+        }
+    }
+    
+    /// Reads and parses a config file.
+    pub fn load<P: AsRef<Utf8Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        if !path.is_file() {
+            return Err(Error::MissingConfig(path.to_path_buf()));
+        }
+        
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+    
+    /// Writes the config to a file.
+    pub fn save<P: AsRef<Utf8Path>>(&self, path: P) -> Result<(), Error> {
+        std::fs::write(path.as_ref(), self.to_json()?)?;
+        
+        Ok(())
+    }
+    
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(&self.root)?)
+    }
+    
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.root.get(key)
+    }
+    
+    /// Sets a top-level field, keeping its position if it already exists.
+    pub fn set<K: Into<String>>(&mut self, key: K, value: Value) {
+        self.root.insert(key.into(), value);
+    }
+    
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        self.root.shift_remove(key)
+    }
+    
+    /// Applies a typed setting.
+    pub fn apply(&mut self, setting: &BizHawkSetting) -> Result<(), Error> {
+        match setting {
+            BizHawkSetting::PreferredCore { system, core } => {
+                self.object_mut("PreferredCores")?.insert(system.clone(), core.clone().into());
+            },
+            BizHawkSetting::Throttle(enabled) => self.set("ClockThrottle", (*enabled).into()),
+            BizHawkSetting::SpeedPercent(percent) => self.set("SpeedPercent", (*percent).into()),
+            BizHawkSetting::Sound(enabled) => self.set("SoundEnabled", (*enabled).into()),
+            BizHawkSetting::FirmwarePath(path) => self.set_path("Global_NULL", "Firmware", path)?,
+            BizHawkSetting::MoviePath(path) => self.set_path("Global_NULL", "Movies", path)?,
+            BizHawkSetting::SavestatePath { system, path } => self.set_path(system, "Savestates", path)?,
+            BizHawkSetting::WindowScale { system, scale } => {
+                self.object_mut("TargetZoomFactors")?.insert(system.clone(), (*scale).into());
+            },
+            BizHawkSetting::Raw { key, value } => self.set(key.clone(), value.clone()),
+        }
+        
+        Ok(())
+    }
+    
+    /// Sets an entry of `PathEntries`, matched by its system and type.
+    pub fn set_path<P: AsRef<Utf8Path>>(&mut self, system: &str, kind: &str, path: P) -> Result<(), Error> {
+        let entries = self.object_mut("PathEntries")?
+            .entry("Paths")
+            .or_insert_with(|| Value::Array(vec![]))
+            .as_array_mut()
+            .ok_or_else(|| Error::UnsupportedSetting("PathEntries.Paths is not an array".into()))?;
+        
+        let path = Value::from(path.as_ref().as_str());
+        let existing = entries.iter_mut()
+            .filter_map(|entry| entry.as_object_mut())
+            .find(|entry| entry.get("System").and_then(Value::as_str) == Some(system) && entry.get("Type").and_then(Value::as_str) == Some(kind));
+        
+        match existing {
+            Some(entry) => { entry.insert("Path".into(), path); },
+            None => {
+                let mut entry = Map::new();
+                entry.insert("Type".into(), kind.into());
+                entry.insert("Path".into(), path);
+                entry.insert("System".into(), system.into());
+                entries.push(Value::Object(entry));
+            },
+        }
+        
+        Ok(())
+    }
+    
+    fn object_mut(&mut self, key: &str) -> Result<&mut Map<String, Value>, Error> {
+        self.root.entry(key)
+            .or_insert_with(|| Value::Object(Map::new()))
+            .as_object_mut()
+            .ok_or_else(|| Error::UnsupportedSetting(format!("{key} is not an object")))
+    }
+}
\ No newline at end of file
@@ -0,0 +1,549 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Deserialize;
+use crate::{CommonOptions, EmulatorContext, Error};
+use crate::doctor::Requirement;
+use crate::env::{Deterministic, EnvInheritance};
+use crate::manifest::Manifest;
+use crate::placement::{require_file, PlacedFiles, Placements};
+use crate::wine::{WineOptions, WinePrefix};
+
+/// Placeholders which can be used in [`CustomContext`] argument templates and environment values.
+pub const PLACEHOLDERS: &[&str] = &["{rom}", "{movie}", "{lua}", "{config}", "{working_dir}"];
+
+/// How a [`CustomContext`]'s executable is started.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Launcher {
+    /// The executable is run directly.
+    #[default]
+    Native,
+    /// The executable is a Windows program, run through Wine on unix and directly on Windows.
+    Wine,
+    /// The executable is a shell script, run by `bash`.
+    Bash,
+}
+
+/// Context for emulators without a dedicated context, built from an executable and argument templates.
+/// 
+/// Argument templates and environment values may contain any of the [`PLACEHOLDERS`], which are replaced by the
+/// paths passed to the emulator. Each template is split on whitespace before the placeholders are replaced,
+/// so a flag and its value can be given together (e.g. `--playmovie {movie}`), and paths may contain spaces.
+/// A template is skipped entirely if any of its placeholders have no value.
+/// 
+/// ```no_run
+/// # use emu_runner::contexts::custom::{CustomContext, Launcher};
+/// let ctx = CustomContext::new("snes9x/", "snes9x-gtk").unwrap()
+///     .with_arg_template("--playmovie {movie}")
+///     .with_arg_template("--luascript={lua}")
+///     .with_arg_template("{rom}")
+///     .with_rom("roms/game.sfc")
+///     .with_movie("game.smv");
+/// 
+/// let ctx = CustomContext::new("dolphin/", "Dolphin.exe").unwrap()
+///     .with_launcher(Launcher::Wine)
+///     .with_arg_template("--exec={rom}")
+///     .with_required_file("Sys/GC/dsp_rom.bin");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomContext {
+    /// Emulator executable, relative to the working directory.
+    pub executable: Utf8PathBuf,
+    
+    /// How the executable is started. Defaults to [`Launcher::Native`].
+    pub launcher: Launcher,
+    
+    /// Templates for the arguments passed to the emulator, in order.
+    pub arg_templates: Vec<String>,
+    
+    /// Environment variables passed to the emulator. Values may contain placeholders.
+    pub env: Vec<(String, String)>,
+    
+    /// Files which must exist before the emulator is run (e.g. BIOS images), relative to the working directory.
+    pub required_files: Vec<Utf8PathBuf>,
+    
+    /// Files copied into the working directory during `prepare`, as `(source, destination)` pairs.
+    /// 
+    /// Destinations are relative to the working directory. The original files are restored during `cleanup`.
+    pub files: Vec<(Utf8PathBuf, Utf8PathBuf)>,
+    pub rom: Option<Utf8PathBuf>,
+    pub movie: Option<Utf8PathBuf>,
+    pub lua: Option<Utf8PathBuf>,
+    
+    /// Config passed to the emulator through the `{config}` placeholder.
+    pub config: Option<Utf8PathBuf>,
+    
+    /// How the ROM, movie and Lua script are made available to the emulator.
+    pub placement: Placements,
+    
+    /// Whether multiple instances may run from the working directory at once.
+    /// 
+    /// When true, [`run`](crate::run) holds a [shared](crate::lock::LockMode::Shared) lock on the working directory for the whole run,
+    /// instead of an exclusive one. `prepare` and `cleanup` still happen one run at a time, under a separate prepare lock.
+    pub concurrent_instances: bool,
+    
+    /// If set, the emulator is run with a [`Deterministic`] environment. Unless [`env_inheritance`](Self::env_inheritance)
+    /// is also set, only [essential](EnvInheritance::essential) variables are then inherited from the parent environment.
+    pub deterministic: Option<Deterministic>,
+    
+    /// Which variables are inherited from the parent environment.
+    /// 
+    /// If not set, only [essential](EnvInheritance::essential) variables are inherited when [`deterministic`](Self::deterministic)
+    /// is set, otherwise everything is inherited.
+    pub env_inheritance: Option<EnvInheritance>,
+    
+    /// Options used when the executable is run through Wine.
+    pub wine: WineOptions,
+    pub working_dir: Utf8PathBuf,
+    placed: PlacedFiles,
+    manifest: Manifest,
+}
+impl EmulatorContext for CustomContext {
+    fn cmd_name(&self) -> String {
+        match self.launcher {
+            Launcher::Native => self.executable_path().to_string(),
+            Launcher::Bash => "bash".into(),
+            
+            #[cfg(target_family = "unix")]
+            Launcher::Wine => self.wine.loader_for(self.executable_path()),
+            
+            #[cfg(target_family = "windows")]
+            Launcher::Wine => self.executable_path().to_string(),
+        }
+    }
+    
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![];
+        
+        if self.launcher == Launcher::Bash {
+            args.push(self.executable_path().to_string());
+        }
+        #[cfg(target_family = "unix")]
+        {
+            if self.launcher == Launcher::Wine {
+                if let Some(deterministic) = self.deterministic.as_ref() {
+                    args.extend(deterministic.wine_desktop_args());
+                }
+                args.push(self.executable_path().to_string());
+            }
+        }
+        
+        for template in &self.arg_templates {
+            let expanded: Option<Vec<String>> = template.split_whitespace().map(|arg| self.expand(arg)).collect();
+            args.extend(expanded.unwrap_or_default());
+        }
+        
+        args
+    }
+    
+    fn env(&self) -> Vec<(String, String)> {
+        let mut vars = vec![];
+        
+        #[cfg(target_family = "unix")]
+        {
+            if self.launcher == Launcher::Wine {
+                vars.extend(self.wine_prefix().env());
+            }
+        }
+        if let Some(deterministic) = self.deterministic.as_ref() {
+            vars.extend(deterministic.vars());
+        }
+        vars.extend(self.env.iter().filter_map(|(key, value)| Some((key.clone(), self.expand(value)?))));
+        
+        vars
+    }
+    
+    fn working_dir(&self) -> Utf8PathBuf {
+        self.working_dir.clone()
+    }
+    
+    fn prepare(&mut self) -> Result<(), Error> {
+        for file in &self.required_files {
+            require_file(self.working_dir.join(file), Error::MissingFile)?;
+        }
+        for (src, dest) in &self.files {
+            require_file(src, Error::MissingFile)?;
+            
+            let dest = self.working_dir.join(dest);
+            if let Some(parent) = dest.parent() {
+                self.manifest.create_dir_all(parent)?;
+            }
+            self.manifest.copy(src, dest)?;
+        }
+        if let Some(config) = self.config.as_ref() {
+            require_file(config, Error::MissingConfig)?;
+        }
+        
+        self.placed = self.placement.place_inputs(self.rom.as_ref(), self.movie.as_ref(), self.lua.as_ref(), &self.working_dir, &mut self.manifest)?;
+        
+        #[cfg(target_family = "unix")]
+        {
+            if self.launcher == Launcher::Wine {
+                self.wine.prepare_prefix(&self.wine_prefix())?;
+            }
+        }
+        
+        Ok(())
+    }
+    
+    fn cleanup(&mut self) -> Result<(), Error> {
+        self.manifest.restore()?;
+        self.placed = PlacedFiles::default();
+        
+        Ok(())
+    }
+    
+    fn set_working_dir(&mut self, working_dir: Utf8PathBuf) -> Result<(), Error> {
+        self.working_dir = working_dir;
+        
+        Ok(())
+    }
+    
+    fn supports_concurrent_instances(&self) -> bool {
+        self.concurrent_instances
+    }
+    
+    fn env_inheritance(&self) -> EnvInheritance {
+        match (self.env_inheritance.as_ref(), self.deterministic.as_ref()) {
+            (Some(inheritance), _) => inheritance.clone(),
+            (None, Some(_)) => EnvInheritance::essential(),
+            (None, None) => EnvInheritance::All,
+        }
+    }
+    
+    fn requirements(&self) -> Vec<Requirement> {
+        match self.launcher {
+            Launcher::Native => vec![Requirement::Executable(self.executable_path())],
+            Launcher::Bash => vec![Requirement::Bash],
+            
+            #[cfg(target_family = "unix")]
+            Launcher::Wine => vec![Requirement::Wine(self.cmd_name()), Requirement::Executable(self.executable_path())],
+            
+            #[cfg(target_family = "windows")]
+            Launcher::Wine => vec![Requirement::Executable(self.executable_path())],
+        }
+    }
+}
+impl CommonOptions for CustomContext {
+    fn set_rom(&mut self, rom: Option<Utf8PathBuf>) {
+        self.rom = rom;
+    }
+    
+    fn set_movie(&mut self, movie: Option<Utf8PathBuf>) {
+        self.movie = movie;
+    }
+    
+    fn set_lua(&mut self, lua: Option<Utf8PathBuf>) {
+        self.lua = lua;
+    }
+    
+    fn set_config(&mut self, config: Option<Utf8PathBuf>) {
+        self.config = config;
+    }
+}
+impl CustomContext {
+    /// Creates a new Context which runs the given executable natively, without any arguments.
+    /// 
+    /// If the path does not point to a directory, or a file within a directory, which contains the executable,
+    /// an error message will be returned.
+    pub fn new<P: Into<Utf8PathBuf>, E: Into<Utf8PathBuf>>(working_dir: P, executable: E) -> Result<Self, Error> {
+        let mut working_dir = working_dir.into();
+        if working_dir.is_file() {
+            working_dir.pop();
+        }
+        
+        working_dir = working_dir.canonicalize_utf8().unwrap_or(working_dir);
+        
+        let executable = executable.into();
+        let detect_exe = working_dir.join(&executable);
+        if working_dir.is_file() || !working_dir.exists() || !detect_exe.is_file() {
+            return Err(Error::MissingExecutable(detect_exe));
+        }
+        
+        Ok(Self {
+            executable,
+            launcher: Launcher::default(),
+            arg_templates: vec![],
+            env: vec![],
+            required_files: vec![],
+            files: vec![],
+            rom: None,
+            movie: None,
+            lua: None,
+            config: None,
+            placement: Placements::default(),
+            concurrent_instances: false,
+            deterministic: None,
+            env_inheritance: None,
+            wine: WineOptions::default(),
+            working_dir,
+            placed: PlacedFiles::default(),
+            manifest: Manifest::new(),
+        })
+    }
+    
+    pub fn with_launcher(self, launcher: Launcher) -> Self {
+        Self {
+            launcher,
+            ..self
+        }
+    }
+    
+    /// Adds an argument template, split on whitespace into one or more arguments.
+    pub fn with_arg_template<S: Into<String>>(mut self, template: S) -> Self {
+        self.arg_templates.push(template.into());
+        self
+    }
+    
+    /// Adds an environment variable. The value may contain placeholders.
+    pub fn with_env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+    
+    /// Adds a file, relative to the working directory, which must exist before the emulator is run.
+    /// 
+    /// If it's missing, `prepare` returns [`Error::MissingFile`].
+    pub fn with_required_file<P: Into<Utf8PathBuf>>(mut self, file: P) -> Self {
+        self.required_files.push(file.into());
+        self
+    }
+    
+    /// Adds a file which is copied into the working directory during `prepare`, at `dest` relative to the working directory.
+    /// 
+    /// If the source is missing, `prepare` returns [`Error::MissingFile`].
+    pub fn with_file<P: Into<Utf8PathBuf>, Q: Into<Utf8PathBuf>>(mut self, src: P, dest: Q) -> Self {
+        let src = src.into();
+        self.files.push((src.canonicalize_utf8().unwrap_or(src), dest.into()));
+        self
+    }
+    
+    /// Same as [`CommonOptions::with_rom`], usable without importing the trait.
+    pub fn with_rom<P: Into<Utf8PathBuf>>(self, rom: P) -> Self {
+        CommonOptions::with_rom(self, rom)
+    }
+    
+    /// Same as [`CommonOptions::with_movie`], usable without importing the trait.
+    pub fn with_movie<P: Into<Utf8PathBuf>>(self, movie: P) -> Self {
+        CommonOptions::with_movie(self, movie)
+    }
+    
+    /// Same as [`CommonOptions::with_lua`], usable without importing the trait.
+    pub fn with_lua<P: Into<Utf8PathBuf>>(self, lua: P) -> Self {
+        CommonOptions::with_lua(self, lua)
+    }
+    
+    /// Same as [`CommonOptions::with_config`], usable without importing the trait.
+    pub fn with_config<P: Into<Utf8PathBuf>>(self, config: P) -> Self {
+        CommonOptions::with_config(self, config)
+    }
+    
+    /// Sets how the ROM, movie and Lua script are made available to the emulator.
+    pub fn with_placement(self, placement: Placements) -> Self {
+        Self {
+            placement,
+            ..self
+        }
+    }
+    
+    /// Sets whether multiple instances may run from the working directory at once.
+    pub fn with_concurrent_instances(self, concurrent_instances: bool) -> Self {
+        Self {
+            concurrent_instances,
+            ..self
+        }
+    }
+    
+    /// Runs the emulator with the given environment preset, to make runs reproducible across machines.
+    pub fn with_deterministic(self, deterministic: Deterministic) -> Self {
+        Self {
+            deterministic: Some(deterministic),
+            ..self
+        }
+    }
+    
+    pub fn with_env_inheritance(self, env_inheritance: EnvInheritance) -> Self {
+        Self {
+            env_inheritance: Some(env_inheritance),
+            ..self
+        }
+    }
+    
+    pub fn with_wine(self, wine: WineOptions) -> Self {
+        Self {
+            wine,
+            ..self
+        }
+    }
+    
+    /// Returns the files written into the emulator's directory by `prepare`, which are restored during `cleanup`.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+    
+    /// Returns the paths of the ROM, movie and Lua script passed to the emulator by the current run.
+    /// 
+    /// Empty until the context has been prepared.
+    pub fn placed(&self) -> &PlacedFiles {
+        &self.placed
+    }
+    
+    /// Returns the Wine prefix used when the executable is run through Wine.
+    pub fn wine_prefix(&self) -> WinePrefix {
+        let mut prefix = self.wine.prefix_for(&self.working_dir, self.executable_path());
+        if let Some(deterministic) = self.deterministic.as_ref() {
+            prefix.dll_overrides.extend(deterministic.wine_dll_overrides());
+        }
+        
+        prefix
+    }
+    
+    fn executable_path(&self) -> Utf8PathBuf {
+        self.working_dir.join(&self.executable)
+    }
+    
+    /// Replaces the placeholders in a template, or returns `None` if any of them have no value.
+    /// 
+    /// The template is scanned once from left to right, so substituted paths are never expanded again.
+    fn expand(&self, template: &str) -> Option<String> {
+        let values = [
+            self.placed.rom.as_ref().or(self.rom.as_ref()),
+            self.placed.movie.as_ref().or(self.movie.as_ref()),
+            self.placed.lua.as_ref().or(self.lua.as_ref()),
+            self.config.as_ref(),
+            Some(&self.working_dir),
+        ];
+        
+        let mut expanded = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            expanded.push_str(&rest[..start]);
+            rest = &rest[start..];
+            
+            match PLACEHOLDERS.iter().zip(values).find(|(placeholder, _)| rest.starts_with(**placeholder)) {
+                Some((placeholder, value)) => {
+                    expanded.push_str(&self.arg_path(value?));
+                    rest = &rest[placeholder.len()..];
+                },
+                None => {
+                    expanded.push('{');
+                    rest = &rest[1..];
+                },
+            }
+        }
+        expanded.push_str(rest);
+        
+        Some(expanded)
+    }
+    
+    /// Formats a file path for the emulator, translating it to a Windows path when run through Wine.
+    fn arg_path(&self, path: &Utf8Path) -> String {
+        #[cfg(target_family = "unix")]
+        {
+            if self.launcher == Launcher::Wine {
+                return self.wine.to_windows_path(path);
+            }
+        }
+        
+        path.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::includes::create_temp_dir;
+    
+    fn with_context<F: FnOnce(CustomContext)>(test: F) {
+        let dir = create_temp_dir("emu-runner-test").unwrap();
+        std::fs::write(dir.join("emu"), "").unwrap();
+        
+        test(CustomContext::new(&dir, "emu").unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+    
+    #[test]
+    fn expands_placeholders() {
+        with_context(|ctx| {
+            let working_dir = ctx.working_dir.clone();
+            let ctx = ctx
+                .with_rom("/nonexistent/roms/Super Mario Bros.nes")
+                .with_movie("/nonexistent/run.fm2");
+            
+            assert_eq!(ctx.expand("--rom={rom}").as_deref(), Some("--rom=/nonexistent/roms/Super Mario Bros.nes"));
+            assert_eq!(ctx.expand("{movie}{movie}").as_deref(), Some("/nonexistent/run.fm2/nonexistent/run.fm2"));
+            assert_eq!(ctx.expand("{working_dir}/saves"), Some(format!("{working_dir}/saves")));
+            assert_eq!(ctx.expand("--fullscreen").as_deref(), Some("--fullscreen"));
+            assert_eq!(ctx.expand("{unknown}").as_deref(), Some("{unknown}"));
+            assert_eq!(ctx.expand("{{rom}}").as_deref(), Some("{/nonexistent/roms/Super Mario Bros.nes}"));
+        });
+    }
+    
+    #[test]
+    fn doesnt_expand_substituted_paths() {
+        with_context(|ctx| {
+            let ctx = ctx
+                .with_rom("/nonexistent/x{lua}.nes")
+                .with_movie("/nonexistent/{rom}.fm2");
+            
+            assert_eq!(ctx.expand("{rom}").as_deref(), Some("/nonexistent/x{lua}.nes"));
+            assert_eq!(ctx.expand("{movie} {rom}").as_deref(), Some("/nonexistent/{rom}.fm2 /nonexistent/x{lua}.nes"));
+        });
+    }
+    
+    #[test]
+    fn expand_fails_on_missing_values() {
+        with_context(|ctx| {
+            let ctx = ctx.with_rom("/nonexistent/game.nes");
+            
+            assert_eq!(ctx.expand("{lua}"), None);
+            assert_eq!(ctx.expand("--rom={rom},lua={lua}"), None);
+            assert_eq!(ctx.expand("{config}"), None);
+        });
+    }
+    
+    #[test]
+    fn skips_templates_with_missing_placeholders() {
+        with_context(|ctx| {
+            let ctx = ctx
+                .with_arg_template("--playmovie {movie}")
+                .with_arg_template("--luascript {lua} --lua-autostart")
+                .with_arg_template("--nogui")
+                .with_arg_template("{rom}")
+                .with_rom("/nonexistent/my roms/game.nes");
+            
+            assert_eq!(ctx.args(), ["--nogui", "/nonexistent/my roms/game.nes"]);
+            
+            let ctx = ctx.with_movie("/nonexistent/run.fm2");
+            assert_eq!(ctx.args(), ["--playmovie", "/nonexistent/run.fm2", "--nogui", "/nonexistent/my roms/game.nes"]);
+        });
+    }
+    
+    #[test]
+    fn skips_env_with_missing_placeholders() {
+        with_context(|ctx| {
+            let ctx = ctx
+                .with_env("EMU_ROM", "{rom}")
+                .with_env("EMU_LUA", "{lua}")
+                .with_env("EMU_MODE", "headless")
+                .with_rom("/nonexistent/game.nes");
+            
+            let env = ctx.env();
+            assert!(env.contains(&("EMU_ROM".into(), "/nonexistent/game.nes".into())));
+            assert!(env.contains(&("EMU_MODE".into(), "headless".into())));
+            assert!(!env.iter().any(|(key, _)| key == "EMU_LUA"));
+        });
+    }
+    
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn translates_paths_for_wine() {
+        with_context(|ctx| {
+            let ctx = ctx
+                .with_launcher(Launcher::Wine)
+                .with_arg_template("-rom {rom}")
+                .with_rom("/nonexistent/roms/game.nes");
+            
+            assert_eq!(ctx.args()[1..], ["-rom", "Z:\\nonexistent\\roms\\game.nes"]);
+        });
+    }
+}
@@ -1,38 +1,167 @@
-use camino::Utf8PathBuf;
-use crate::{EmulatorContext, Error};
-use crate::includes::copy_if_different;
+use camino::{Utf8Path, Utf8PathBuf};
+use crate::{CommonOptions, EmulatorContext, Error};
+use crate::doctor::Requirement;
+use crate::env::{Deterministic, EnvInheritance};
+use crate::includes::create_temp_dir;
+use crate::manifest::Manifest;
+use crate::placement::{require_file, PlacedFiles, Placements};
+use crate::wine::{WineOptions, WinePrefix};
+
+pub mod config;
+
+use config::{FceuxConfig, FceuxConfigFormat, FceuxSetting};
+
+/// Family of an FCEUX executable, which determines its CLI arguments and config location.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FceuxBuild {
+    /// Native SDL/Qt binary (`fceux`).
+    Native,
+    /// Win32/Win64 builds (`fceux.exe`, `fceux64.exe`).
+    Windows,
+    /// Windows Qt/SDL build (`qfceux.exe`).
+    Qt,
+}
+impl FceuxBuild {
+    /// Returns the format of the `fceux.cfg` file read by this build.
+    pub fn config_format(&self) -> FceuxConfigFormat {
+        match self {
+            FceuxBuild::Native | FceuxBuild::Qt => FceuxConfigFormat::Sdl,
+            FceuxBuild::Windows => FceuxConfigFormat::Win32,
+        }
+    }
+    
+    pub fn from_executable_name(name: &str) -> Self {
+        let name = name.to_ascii_lowercase();
+        
+        if !name.ends_with(".exe") {
+            FceuxBuild::Native
+        } else if name.starts_with("qfceux") {
+            FceuxBuild::Qt
+        } else {
+            FceuxBuild::Windows
+        }
+    }
+}
+
+/// Policy used to choose an executable when an FCEUX folder contains multiple builds.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum FceuxExecutable {
+    /// Prefers the native binary, then Windows builds: `fceux` > `fceux.exe` > `fceux64.exe` > `qfceux.exe`.
+    #[default]
+    NativeFirst,
+    /// Only uses the Win32/Win64 builds: `fceux.exe` > `fceux64.exe`.
+    WindowsOnly,
+    /// Only uses the Qt/SDL builds: `qfceux.exe` > `fceux`.
+    QtOnly,
+    /// Only uses the executable with this file name.
+    Explicit(String),
+}
+impl FceuxExecutable {
+    /// Returns the executable names accepted by this policy, in order of preference.
+    pub fn candidates(&self) -> Vec<String> {
+        match self {
+            FceuxExecutable::NativeFirst => vec!["fceux".into(), "fceux.exe".into(), "fceux64.exe".into(), "qfceux.exe".into()],
+            FceuxExecutable::WindowsOnly => vec!["fceux.exe".into(), "fceux64.exe".into()],
+            FceuxExecutable::QtOnly => vec!["qfceux.exe".into(), "fceux".into()],
+            FceuxExecutable::Explicit(name) => vec![name.clone()],
+        }
+    }
+}
+
+/// Directory FCEUX reads and writes its configuration in.
+/// 
+/// Native builds are pointed at it with `FCEUX_HOME` (and `XDG_CONFIG_HOME` for Qt's own settings),
+/// so the user's `HOME` is left untouched. Win32/Win64 builds are given a config inside it via `-cfg`,
+/// which starts as a copy of the `fceux.cfg` beside the executable. The Windows Qt build (`qfceux.exe`) only reads
+/// the `fceux.cfg` beside its executable, so it can't be isolated this way; `prepare` returns
+/// [`Error::UnsupportedSetting`] for any home other than [`Shared`](FceuxConfigHome::Shared).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum FceuxConfigHome {
+    /// `<working_dir>/`, shared by every run of this install.
+    #[default]
+    Shared,
+    /// A new temporary directory for every run, removed once the run has finished.
+    Temporary,
+    /// A persistent, named profile stored in `<working_dir>/.emu-runner/profiles/<name>/`.
+    /// 
+    /// The name must be a single path component; names containing path separators, or `.`/`..`, are rejected by `prepare`.
+    Profile(String),
+    /// A specific directory.
+    Dir(Utf8PathBuf),
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct FceuxContext {
     pub config: Option<Utf8PathBuf>,
+    
+    /// Settings applied on top of the base config during `prepare`.
+    /// 
+    /// The base config is [`config`](FceuxContext::config) if set, otherwise the config the emulator would currently use.
+    pub config_overrides: Vec<FceuxSetting>,
     pub movie: Option<Utf8PathBuf>,
     pub lua: Option<Utf8PathBuf>,
     pub rom: Option<Utf8PathBuf>,
     
+    /// How the ROM, movie and Lua script are made available to the emulator.
+    pub placement: Placements,
+    
     /// If set, forces Old (false) or New (true) PPU mode.
     /// 
-    /// **Note:** Only used when executable is `fceux` (linux binary) or `qfceux.exe`
+    /// **Note:** Only used by the [`Native`](FceuxBuild::Native) and [`Qt`](FceuxBuild::Qt) builds.
     pub ppu_mode: Option<bool>,
+    
+    /// Policy used to choose which executable is run.
+    pub executable: FceuxExecutable,
+    
+    /// Where the emulator's configuration is kept.
+    pub config_home: FceuxConfigHome,
+    
+    /// Whether multiple instances may run from the working directory at once.
+    /// 
+    /// When true, [`run`](crate::run) holds a [shared](crate::lock::LockMode::Shared) lock on the working directory for the whole run,
+    /// instead of an exclusive one. `prepare` and `cleanup` still happen one run at a time, under a separate prepare lock.
+    pub concurrent_instances: bool,
+    
+    /// If set, the emulator is run with a [`Deterministic`] environment. Unless [`env_inheritance`](Self::env_inheritance)
+    /// is also set, only [essential](EnvInheritance::essential) variables are then inherited from the parent environment.
+    pub deterministic: Option<Deterministic>,
+    
+    /// Which variables are inherited from the parent environment.
+    /// 
+    /// If not set, only [essential](EnvInheritance::essential) variables are inherited when [`deterministic`](Self::deterministic)
+    /// is set, otherwise everything is inherited.
+    pub env_inheritance: Option<EnvInheritance>,
+    
+    /// Additional environment variables, applied after any set by emu-runner.
+    pub extra_env: Vec<(String, String)>,
+    
+    /// Additional arguments passed to the emulator before the ROM. Paths are passed as-is, even when run through Wine.
+    pub extra_args: Vec<String>,
+    
+    /// Additional arguments passed to the emulator after the ROM.
+    pub trailing_args: Vec<String>,
+    temp_home: Option<Utf8PathBuf>,
+    placed: PlacedFiles,
+    manifest: Manifest,
+    
+    /// Options used when a Windows build is run through Wine.
+    pub wine: WineOptions,
     pub working_dir: Utf8PathBuf,
 }
 impl EmulatorContext for FceuxContext {
     fn cmd_name(&self) -> String {
+        let exe = self.executable_name();
+        
         #[cfg(target_family = "unix")]
         {
-            match self.determine_executable() {
-                Some(exe) if exe == "fceux" => "./fceux".into(),
-                Some(_) => "wine".into(),
-                None => "./fceux".into(),
+            match FceuxBuild::from_executable_name(&exe) {
+                FceuxBuild::Native => format!("./{exe}"),
+                _ => self.wine.loader_for(self.working_dir.join(exe)),
             }
         }
         
         #[cfg(target_family = "windows")]
-        {
-            match self.determine_executable() {
-                Some(exe) => exe,
-                None => "fceux.exe".into()
-            }
-        }
+        { exe }
     }
     
     fn args(&self) -> Vec<String> {
@@ -40,49 +169,52 @@ impl EmulatorContext for FceuxContext {
         
         #[cfg(target_family = "unix")]
         {
-            if self.cmd_name() == "wine" {
+            if self.uses_wine() {
+                if let Some(deterministic) = self.deterministic.as_ref() {
+                    args.extend(deterministic.wine_desktop_args());
+                }
                 args.push(self.determine_executable().unwrap());
             }
         }
         
-        match self.determine_executable() {
-            Some(exe) => match exe.as_str() {
-                "fceux.exe" | "fceux64.exe" => {
-                    if let Some(config) = self.config.as_ref() {
+        if let Some(build) = self.build() {
+            match build {
+                FceuxBuild::Windows => {
+                    if let Some(config) = self.config_path().or_else(|| self.config.clone()) {
                         args.push("-cfg".into());
-                        args.push(config.to_string());
+                        args.push(self.arg_path(&config));
                     }
-                    if let Some(movie) = self.movie.as_ref() {
+                    if let Some(movie) = self.placed.movie.as_ref().or(self.movie.as_ref()) {
                         args.push("-playmovie".into());
-                        args.push(movie.to_string());
+                        args.push(self.arg_path(movie));
                     }
-                    if let Some(lua) = self.lua.as_ref() {
+                    if let Some(lua) = self.placed.lua.as_ref().or(self.lua.as_ref()) {
                         args.push("-lua".into());
-                        args.push(lua.to_string());
+                        args.push(self.arg_path(lua));
                     }
                 },
-                "fceux" | "qfceux.exe" => {
-                    if let Some(movie) = self.movie.as_ref() {
+                FceuxBuild::Native | FceuxBuild::Qt => {
+                    if let Some(movie) = self.placed.movie.as_ref().or(self.movie.as_ref()) {
                         args.push("--playmov".into());
-                        args.push(movie.to_string());
+                        args.push(self.arg_path(movie));
                     }
-                    if let Some(lua) = self.lua.as_ref() {
+                    if let Some(lua) = self.placed.lua.as_ref().or(self.lua.as_ref()) {
                         args.push("--loadlua".into());
-                        args.push(lua.to_string());
+                        args.push(self.arg_path(lua));
                     }
                     if let Some(ppu_mode) = self.ppu_mode.as_ref() {
                         args.push("--newppu".into());
                         args.push(if *ppu_mode { "1".into() } else { "0".into() });
                     }
                 },
-                _ => ()
-            },
-            None => ()
+            }
         }
         
-        if let Some(rom) = self.rom.as_ref() {
-            args.push(rom.to_string());
+        args.extend(self.extra_args.iter().cloned());
+        if let Some(rom) = self.placed.rom.as_ref().or(self.rom.as_ref()) {
+            args.push(self.arg_path(rom));
         }
+        args.extend(self.trailing_args.iter().cloned());
         
         args
     }
@@ -92,94 +224,116 @@ impl EmulatorContext for FceuxContext {
 
         #[cfg(target_family = "unix")]
         {
-            if self.cmd_name() == "wine" {
-                let mut prefix = self.working_dir();
-                prefix.push(".wine/");
-                
-                vars.push(("WINEPREFIX".into(), prefix.to_string()));
+            if self.uses_wine() {
+                vars.extend(self.wine_prefix().env());
             }
         }
         
-        let mut home = self.working_dir();
-        home.push(".fceux/");
-        vars.push(("HOME".into(), home.to_string()));
+        if self.build() == Some(FceuxBuild::Native) {
+            if let Some(home) = self.config_home_dir() {
+                vars.push(("FCEUX_HOME".into(), home.to_string()));
+                vars.push(("XDG_CONFIG_HOME".into(), home.join(".config").to_string()));
+            }
+        }
+        if let Some(deterministic) = self.deterministic.as_ref() {
+            vars.extend(deterministic.vars());
+        }
+        vars.extend(self.extra_env.iter().cloned());
         
         vars
     }
     
     fn prepare(&mut self) -> Result<(), Error> {
         // FCEUX accepts configs/movies/scripts/roms from anywhere,
-        // so we only need to verify they exist.
+        // so we only need to verify they exist, then place them according to the policy.
         // However, since we change the working directory, and there's no
         // easy way to test if file exists relative to a different dir,
         // the paths _should_ be absolute, either originally or via the with_* functions.
         
+        if self.determine_executable().is_none() {
+            return Err(Error::MissingExecutable(self.working_dir.join(self.executable_name())));
+        }
+        
         #[cfg(target_family = "windows")]
         {
-            if cmd_name == "./fceux" {
+            if self.build() == Some(FceuxBuild::Native) {
                 return Err(Error::IncompatibleOSVersion);
             }
         }
         
+        if let FceuxConfigHome::Profile(name) = &self.config_home {
+            if !is_valid_profile_name(name) {
+                return Err(Error::UnsupportedSetting(format!("profile = {name}")));
+            }
+        }
+        if self.build() == Some(FceuxBuild::Qt) && self.config_home != FceuxConfigHome::Shared {
+            return Err(Error::UnsupportedSetting(format!("config_home = {:?}", self.config_home)));
+        }
+        if self.config_home == FceuxConfigHome::Temporary && self.temp_home.is_none() {
+            self.temp_home = Some(create_temp_dir("emu-runner-fceux")?);
+        }
+        
         if let Some(config) = self.config.as_ref() {
             // Preparing the config file is extremely messy.
             // - win32/win64 provides a CLI argument that is used.
             // - win64-QtSLD uses the fceux.cfg located beside the executable.
-            // - compiled linux builds use $HOME/.fceux/fceux.cfg.
-            //     (if $HOME isn't set, it's unclear what FCEUX does)
+            // - compiled linux builds use $FCEUX_HOME/.fceux/fceux.cfg, falling back to $HOME.
             
-            if !config.is_file() {
-                return Err(Error::MissingConfig(config.clone()));
+            require_file(config, Error::MissingConfig)?;
+            if self.config_path().is_none() && !config.is_absolute() {
+                return Err(Error::AbsolutePathFailed);
+            }
+        }
+        if let (Some(dest), Some(build)) = (self.config_path(), self.build()) {
+            if let Some(parent) = dest.parent() {
+                self.manifest.create_dir_all(parent)?;
             }
             
-            if let Some(config) = self.config.as_ref() {
-                if !config.is_file() {
-                    return Err(Error::MissingConfig(config.clone()));
-                }
-                
-                if let Some(exe) = self.determine_executable() {
-                    let mut dest = self.working_dir();
-                    if exe == "fceux" {
-                        dest.push(".fceux/");
-                        if !dest.is_dir() {
-                            std::fs::create_dir_all(&dest)?;
-                        }
-                        dest.push("fceux.cfg");
-                        
-                        copy_if_different(&std::fs::read(config)?, dest)?;
-                    } else if exe == "qfceux.exe" {
-                        dest.push("fceux.cfg");
-                        
-                        copy_if_different(&std::fs::read(config)?, dest)?;
-                    } else if !config.is_absolute() {
-                        return Err(Error::AbsolutePathFailed);
+            if self.config_overrides.is_empty() {
+                if let Some(config) = self.config.as_ref() {
+                    self.manifest.copy(config, dest)?;
+                } else if build == FceuxBuild::Windows && !dest.is_file() {
+                    // the home's config is kept, so a profile remembers settings between runs
+                    let base = self.current_config(&dest, build);
+                    if base.is_file() {
+                        std::fs::copy(base, dest)?;
                     }
                 }
+            } else {
+                let base = match self.config.clone() {
+                    Some(config) => config,
+                    None => self.current_config(&dest, build),
+                };
+                let mut config = if base.is_file() {
+                    FceuxConfig::load(&base, build.config_format())?
+                } else {
+                    FceuxConfig::new(build.config_format())
+                };
+                for setting in &self.config_overrides {
+                    config.apply(setting)?;
+                }
+                
+                self.manifest.write(&config.to_bytes()?, dest)?;
             }
         }
-        if let Some(movie) = self.movie.as_ref() {
-            if !movie.is_file() {
-                return Err(Error::MissingMovie(movie.clone()));
-            }
-            if !movie.is_absolute() {
-                return Err(Error::AbsolutePathFailed);
-            }
-        }
-        if let Some(lua) = self.lua.as_ref() {
-            if !lua.is_file() {
-                return Err(Error::MissingLua(lua.clone()));
-            }
-            if !lua.is_absolute() {
-                return Err(Error::AbsolutePathFailed);
+        self.placed = self.placement.place_inputs(self.rom.as_ref(), self.movie.as_ref(), self.lua.as_ref(), &self.working_dir, &mut self.manifest)?;
+        
+        #[cfg(target_family = "unix")]
+        {
+            if self.uses_wine() {
+                self.wine.prepare_prefix(&self.wine_prefix())?;
             }
         }
-        if let Some(rom) = self.rom.as_ref() {
-            if !rom.is_file() {
-                return Err(Error::MissingRom(rom.clone()));
-            }
-            if !rom.is_absolute() {
-                return Err(Error::AbsolutePathFailed);
-            }
+        
+        Ok(())
+    }
+    
+    fn cleanup(&mut self) -> Result<(), Error> {
+        self.manifest.restore()?;
+        self.placed = PlacedFiles::default();
+        
+        if let Some(home) = self.temp_home.take() {
+            std::fs::remove_dir_all(home)?;
         }
         
         Ok(())
@@ -188,6 +342,54 @@ impl EmulatorContext for FceuxContext {
     fn working_dir(&self) -> Utf8PathBuf {
         self.working_dir.clone()
     }
+    
+    fn set_working_dir(&mut self, working_dir: Utf8PathBuf) -> Result<(), Error> {
+        self.working_dir = working_dir;
+        
+        Ok(())
+    }
+    
+    fn supports_concurrent_instances(&self) -> bool {
+        self.concurrent_instances
+    }
+    
+    fn env_inheritance(&self) -> EnvInheritance {
+        match (self.env_inheritance.as_ref(), self.deterministic.as_ref()) {
+            (Some(inheritance), _) => inheritance.clone(),
+            (None, Some(_)) => EnvInheritance::essential(),
+            (None, None) => EnvInheritance::All,
+        }
+    }
+    
+    fn requirements(&self) -> Vec<Requirement> {
+        let executable = Requirement::Executable(self.working_dir.join(self.executable_name()));
+        
+        #[cfg(target_family = "unix")]
+        {
+            if self.uses_wine() {
+                return vec![Requirement::Wine(self.cmd_name()), executable];
+            }
+        }
+        
+        vec![executable]
+    }
+}
+impl CommonOptions for FceuxContext {
+    fn set_rom(&mut self, rom: Option<Utf8PathBuf>) {
+        self.rom = rom;
+    }
+    
+    fn set_movie(&mut self, movie: Option<Utf8PathBuf>) {
+        self.movie = movie;
+    }
+    
+    fn set_lua(&mut self, lua: Option<Utf8PathBuf>) {
+        self.lua = lua;
+    }
+    
+    fn set_config(&mut self, config: Option<Utf8PathBuf>) {
+        self.config = config;
+    }
 }
 impl FceuxContext {
     /// Creates a new Context with default options.
@@ -195,6 +397,14 @@ impl FceuxContext {
     /// If the path does not point to a directory, or a file within a directory, which contains a valid FCEUX executable,
     /// an error will be returned.
     pub fn new<P: Into<Utf8PathBuf>>(working_dir: P) -> Result<Self, Error> {
+        Self::new_with_executable(working_dir, FceuxExecutable::default())
+    }
+    
+    /// Creates a new Context with default options, which runs an executable chosen by the given policy.
+    /// 
+    /// If the path does not point to a directory, or a file within a directory, which contains an executable
+    /// accepted by the policy (e.g. the name given to [`FceuxExecutable::Explicit`]), an error will be returned.
+    pub fn new_with_executable<P: Into<Utf8PathBuf>>(working_dir: P, executable: FceuxExecutable) -> Result<Self, Error> {
         let mut working_dir = working_dir.into();
         if working_dir.is_file() {
             working_dir.pop();
@@ -206,96 +416,256 @@ impl FceuxContext {
             return Err(Error::MissingExecutable(working_dir));
         }
         
-        let mut found = false;
-        for exe in ["fceux.exe", "fceux64.exe", "qfceux.exe", "fceux"] {
-            let mut path = working_dir.clone();
-            path.push(exe);
-            
-            if path.is_file() {
-                found = true;
-                break;
-            }
-        }
-        if !found {
-            let mut path = working_dir.clone();
-            path.push("fceux");
-            return Err(Error::MissingExecutable(path));
+        let candidates = executable.candidates();
+        if !candidates.iter().any(|exe| working_dir.join(exe).is_file()) {
+            let name = candidates.first().map(|exe| exe.as_str()).unwrap_or("fceux");
+            return Err(Error::MissingExecutable(working_dir.join(name)));
         }
         
         Ok(Self {
             config: None,
+            config_overrides: vec![],
             movie: None,
             lua: None,
             rom: None,
+            placement: Placements::default(),
             ppu_mode: None,
+            executable,
+            config_home: FceuxConfigHome::default(),
+            concurrent_instances: false,
+            deterministic: None,
+            env_inheritance: None,
+            extra_env: vec![],
+            extra_args: vec![],
+            trailing_args: vec![],
+            temp_home: None,
+            placed: PlacedFiles::default(),
+            manifest: Manifest::new(),
+            wine: WineOptions::default(),
             working_dir,
         })
     }
     
+    /// Adds a setting to apply on top of the base config.
+    pub fn with_config_override(mut self, setting: FceuxSetting) -> Self {
+        self.config_overrides.push(setting);
+        self
+    }
+    
+    /// Same as [`CommonOptions::with_rom`], usable without importing the trait.
+    pub fn with_rom<P: Into<Utf8PathBuf>>(self, rom: P) -> Self {
+        CommonOptions::with_rom(self, rom)
+    }
+    
+    /// Same as [`CommonOptions::with_movie`], usable without importing the trait.
+    pub fn with_movie<P: Into<Utf8PathBuf>>(self, movie: P) -> Self {
+        CommonOptions::with_movie(self, movie)
+    }
+    
+    /// Same as [`CommonOptions::with_lua`], usable without importing the trait.
+    pub fn with_lua<P: Into<Utf8PathBuf>>(self, lua: P) -> Self {
+        CommonOptions::with_lua(self, lua)
+    }
+    
+    /// Same as [`CommonOptions::with_config`], usable without importing the trait.
     pub fn with_config<P: Into<Utf8PathBuf>>(self, config: P) -> Self {
-        let config = config.into();
+        CommonOptions::with_config(self, config)
+    }
+    
+    /// Sets how the ROM, movie and Lua script are made available to the emulator.
+    pub fn with_placement(self, placement: Placements) -> Self {
         Self {
-            config: Some(config.canonicalize_utf8().unwrap_or_else(|_| config)),
+            placement,
             ..self
         }
     }
     
-    pub fn with_movie<P: Into<Utf8PathBuf>>(self, movie: P) -> Self {
-        let movie = movie.into();
+    pub fn with_ppu_mode(self, ppu_mode: bool) -> Self {
         Self {
-            movie: Some(movie.canonicalize_utf8().unwrap_or_else(|_| movie)),
+            ppu_mode: Some(ppu_mode),
             ..self
         }
     }
     
-    pub fn with_lua<P: Into<Utf8PathBuf>>(self, lua: P) -> Self {
-        let lua = lua.into();
+    /// Chooses which executable is run when the folder contains multiple FCEUX builds.
+    pub fn with_executable(self, executable: FceuxExecutable) -> Self {
         Self {
-            lua: Some(lua.canonicalize_utf8().unwrap_or_else(|_| lua)),
+            executable,
             ..self
         }
     }
     
-    pub fn with_rom<P: Into<Utf8PathBuf>>(self, rom: P) -> Self {
-        let rom = rom.into();
+    /// Chooses where the emulator's configuration is kept, e.g. a temporary directory per run.
+    pub fn with_config_home(self, config_home: FceuxConfigHome) -> Self {
         Self {
-            rom: Some(rom.canonicalize_utf8().unwrap_or_else(|_| rom)),
+            config_home,
             ..self
         }
     }
     
-    pub fn with_ppu_mode(self, ppu_mode: bool) -> Self {
+    /// Sets whether multiple instances may run from the working directory at once.
+    pub fn with_concurrent_instances(self, concurrent_instances: bool) -> Self {
         Self {
-            ppu_mode: Some(ppu_mode),
+            concurrent_instances,
+            ..self
+        }
+    }
+    
+    /// Runs the emulator with the given environment preset, to make runs reproducible across machines.
+    pub fn with_deterministic(self, deterministic: Deterministic) -> Self {
+        Self {
+            deterministic: Some(deterministic),
             ..self
         }
     }
     
+    pub fn with_env_inheritance(self, env_inheritance: EnvInheritance) -> Self {
+        Self {
+            env_inheritance: Some(env_inheritance),
+            ..self
+        }
+    }
+    
+    /// Adds an environment variable, overriding any set by emu-runner.
+    pub fn with_env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.extra_env.push((key.into(), value.into()));
+        self
+    }
+    
+    /// Adds an argument passed to the emulator before the ROM.
+    pub fn with_extra_arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.extra_args.push(arg.into());
+        self
+    }
+    
+    /// Adds an argument passed to the emulator after the ROM.
+    pub fn with_trailing_arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.trailing_args.push(arg.into());
+        self
+    }
+    
+    pub fn with_wine(self, wine: WineOptions) -> Self {
+        Self {
+            wine,
+            ..self
+        }
+    }
+    
+    /// Maps a drive letter to a host directory for paths passed to Wine.
+    pub fn with_wine_drive<P: Into<Utf8PathBuf>>(self, letter: char, dir: P) -> Self {
+        Self {
+            wine: self.wine.with_drive(letter, dir),
+            ..self
+        }
+    }
+    
+    /// Returns the file name of the executable chosen by the [executable policy](FceuxContext::executable).
     pub fn determine_executable(&self) -> Option<String> {
-        let mut path = self.working_dir();
-        path.push("fceux");
-        if path.is_file() {
-            return Some("fceux".into())
+        self.executable.candidates().into_iter().find(|exe| self.working_dir.join(exe).is_file())
+    }
+    
+    /// Returns the chosen executable, or the policy's preferred one if none of them exist.
+    fn executable_name(&self) -> String {
+        self.determine_executable()
+            .or_else(|| self.executable.candidates().into_iter().next())
+            .unwrap_or_default()
+    }
+    
+    /// Returns the family of the chosen executable.
+    pub fn build(&self) -> Option<FceuxBuild> {
+        self.determine_executable().map(|exe| FceuxBuild::from_executable_name(&exe))
+    }
+    
+    /// Returns the directory used as FCEUX's home, if it is known.
+    /// 
+    /// A [`Temporary`](FceuxConfigHome::Temporary) home is only known once the context has been prepared,
+    /// and a [`Profile`](FceuxConfigHome::Profile) with an invalid name has no home.
+    pub fn config_home_dir(&self) -> Option<Utf8PathBuf> {
+        match &self.config_home {
+            FceuxConfigHome::Shared => Some(self.working_dir()),
+            FceuxConfigHome::Temporary => self.temp_home.clone(),
+            FceuxConfigHome::Profile(name) if is_valid_profile_name(name) => Some(self.working_dir.join(".emu-runner/profiles").join(name)),
+            FceuxConfigHome::Profile(_) => None,
+            FceuxConfigHome::Dir(dir) => Some(dir.clone()),
         }
-        
-        path.pop();
-        path.push("fceux.exe");
-        if path.is_file() {
-            return Some("fceux.exe".into())
+    }
+    
+    /// Returns the path the chosen build reads its config file from.
+    /// 
+    /// Returns `None` if there is no config to pass, or it is passed to the emulator where it is, rather than copied into place.
+    pub fn config_path(&self) -> Option<Utf8PathBuf> {
+        match self.build()? {
+            FceuxBuild::Native => Some(self.config_home_dir()?.join(".fceux/fceux.cfg")),
+            FceuxBuild::Qt => Some(self.working_dir.join("fceux.cfg")),
+            FceuxBuild::Windows if self.config_home != FceuxConfigHome::Shared => Some(self.config_home_dir()?.join("fceux.cfg")),
+            FceuxBuild::Windows if !self.config_overrides.is_empty() => Some(self.working_dir.join(".emu-runner/fceux.cfg")),
+            FceuxBuild::Windows => None,
         }
+    }
+    
+    /// Returns the config the emulator would currently use, which overrides are applied to when no config is given.
+    /// 
+    /// Win32/Win64 builds fall back to the `fceux.cfg` beside the executable, unless the config home already has its own.
+    fn current_config(&self, dest: &Utf8Path, build: FceuxBuild) -> Utf8PathBuf {
+        // if this context was already prepared, build on the original rather than the generated config
+        let existing = self.manifest.original(dest).map(|original| original.to_path_buf()).unwrap_or_else(|| dest.to_path_buf());
         
-        path.pop();
-        path.push("fceux64.exe");
-        if path.is_file() {
-            return Some("fceux64.exe".into())
+        match build {
+            FceuxBuild::Windows if !existing.is_file() => self.working_dir.join("fceux.cfg"),
+            _ => existing,
+        }
+    }
+    
+    /// Returns true if a config file or config overrides have been provided.
+    pub fn has_config(&self) -> bool {
+        self.config.is_some() || !self.config_overrides.is_empty()
+    }
+    
+    /// Returns the files written by `prepare`, which are restored during `cleanup`.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+    
+    /// Returns the paths of the ROM, movie and Lua script passed to the emulator by the current run.
+    /// 
+    /// Empty until the context has been prepared.
+    pub fn placed(&self) -> &PlacedFiles {
+        &self.placed
+    }
+    
+    /// Returns the Wine prefix used when a Windows build is run through Wine.
+    pub fn wine_prefix(&self) -> WinePrefix {
+        let mut prefix = self.wine.prefix_for(self.working_dir(), self.working_dir.join(self.executable_name()));
+        if let Some(deterministic) = self.deterministic.as_ref() {
+            prefix.dll_overrides.extend(deterministic.wine_dll_overrides());
         }
         
-        path.pop();
-        path.push("qfceux.exe");
-        if path.is_file() {
-            return Some("qfceux.exe".into())
+        prefix
+    }
+    
+    /// Returns true if the emulator is a Windows build launched through Wine.
+    pub fn uses_wine(&self) -> bool {
+        #[cfg(target_family = "unix")]
+        { matches!(self.build(), Some(build) if build != FceuxBuild::Native) }
+        
+        #[cfg(target_family = "windows")]
+        { false }
+    }
+    
+    /// Formats a file path for the emulator's command line, translating it to a Windows path when run through Wine.
+    fn arg_path(&self, path: &Utf8Path) -> String {
+        #[cfg(target_family = "unix")]
+        {
+            if self.uses_wine() {
+                return self.wine.to_windows_path(path);
+            }
         }
         
-        None
+        path.to_string()
     }
+}
+
+/// Returns true if the profile name is a single, normal path component, so the profile stays inside `.emu-runner/profiles/`.
+fn is_valid_profile_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', ':'])
 }
\ No newline at end of file
@@ -0,0 +1,291 @@
+use std::fmt::{Display, Formatter};
+use camino::Utf8Path;
+use crate::Error;
+
+/// Layout of an `fceux.cfg` file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FceuxConfigFormat {
+    /// `key = value` lines, encoded as UTF-8, used by the native SDL/Qt builds and `qfceux.exe`.
+    Sdl,
+    /// `key value` lines, used by the Win32/Win64 builds.
+    /// 
+    /// These are written in the system's ANSI code page, so each byte is read as the character with the same value
+    /// (Latin-1), which keeps any code page intact when the file is written back.
+    Win32,
+}
+impl FceuxConfigFormat {
+    /// Decodes the contents of a config file in this format.
+    /// 
+    /// Returns an error if an SDL config isn't valid UTF-8, rather than replacing characters.
+    pub fn decode(&self, bytes: &[u8]) -> Result<String, Error> {
+        match self {
+            FceuxConfigFormat::Sdl => String::from_utf8(bytes.to_vec())
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err).into()),
+            FceuxConfigFormat::Win32 => Ok(bytes.iter().map(|byte| *byte as char).collect()),
+        }
+    }
+    
+    /// Encodes config text in this format.
+    /// 
+    /// Returns [`Error::UnsupportedSetting`] if a Win32 config contains a character outside of Latin-1.
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, Error> {
+        match self {
+            FceuxConfigFormat::Sdl => Ok(text.as_bytes().to_vec()),
+            FceuxConfigFormat::Win32 => text.chars()
+                .map(|c| u8::try_from(c).map_err(|_| Error::UnsupportedSetting(format!("character {c:?} in a Win32 config"))))
+                .collect(),
+        }
+    }
+}
+
+/// Console region emulated by FCEUX.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FceuxRegion {
+    Ntsc,
+    Pal,
+}
+
+/// A single typed change to an `fceux.cfg` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FceuxSetting {
+    /// Enables or disables sound output.
+    Sound(bool),
+    /// Selects the New (true) or Old (false) PPU.
+    NewPpu(bool),
+    /// Selects the emulated region.
+    Region(FceuxRegion),
+    /// Device plugged into an input port (e.g. `GamePad.0`, `Zapper`, `None`).
+    /// 
+    /// **Note:** Only supported by the SDL format.
+    Input { port: u8, device: String },
+    /// Any other key, such as a path setting, written as-is.
+    Raw { key: String, value: String },
+}
+impl FceuxSetting {
+    /// Returns the key and value this setting is stored as, or `None` if the format doesn't support it.
+    pub fn entry(&self, format: FceuxConfigFormat) -> Option<(String, String)> {
+        use FceuxConfigFormat::*;
+        
+        let flag = |enabled: bool| if enabled { "1".to_string() } else { "0".to_string() };
+        
+        Some(match (self, format) {
+            (FceuxSetting::Sound(enabled), Sdl) => ("SDL.Sound".into(), flag(*enabled)),
+            (FceuxSetting::Sound(enabled), Win32) => ("sound".into(), flag(*enabled)),
+            (FceuxSetting::NewPpu(enabled), Sdl) => ("SDL.NewPPU".into(), flag(*enabled)),
+            (FceuxSetting::NewPpu(enabled), Win32) => ("newppu".into(), flag(*enabled)),
+            (FceuxSetting::Region(region), Sdl) => ("SDL.PAL".into(), flag(*region == FceuxRegion::Pal)),
+            (FceuxSetting::Region(region), Win32) => ("palyo".into(), flag(*region == FceuxRegion::Pal)),
+            (FceuxSetting::Input { port, device }, Sdl) => (format!("SDL.Input.{port}"), device.clone()),
+            (FceuxSetting::Input { .. }, Win32) => return None,
+            (FceuxSetting::Raw { key, value }, _) => (key.clone(), value.clone()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    Entry { key: String, value: String },
+    Other(String),
+}
+
+/// An `fceux.cfg` file which can be edited key by key.
+/// 
+/// Lines that aren't settings (comments, blank lines) and the order of existing keys are preserved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FceuxConfig {
+    pub format: FceuxConfigFormat,
+    lines: Vec<Line>,
+}
+impl FceuxConfig {
+    /// Creates an empty config.
+    pub fn new(format: FceuxConfigFormat) -> Self {
+        Self {
+            format,
+            lines: vec![],
+        }
+    }
+    
+    pub fn parse(text: &str, format: FceuxConfigFormat) -> Self {
+        let lines = text.lines().map(|line| {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                return Line::Other(line.to_string());
+            }
+            
+            let split = match format {
+                FceuxConfigFormat::Sdl => trimmed.split_once('='),
+                FceuxConfigFormat::Win32 => trimmed.split_once(' ').or(Some((trimmed, ""))),
+            };
+            
+            match split {
+                Some((key, value)) => Line::Entry { key: key.trim().to_string(), value: value.trim().to_string() },
+                None => Line::Other(line.to_string()),
+            }
+        }).collect();
+        
+        Self {
+            format,
+            lines,
+        }
+    }
+    
+    /// Reads and parses a config file.
+    pub fn load<P: AsRef<Utf8Path>>(path: P, format: FceuxConfigFormat) -> Result<Self, Error> {
+        let path = path.as_ref();
+        if !path.is_file() {
+            return Err(Error::MissingConfig(path.to_path_buf()));
+        }
+        
+        Ok(Self::parse(&format.decode(&std::fs::read(path)?)?, format))
+    }
+    
+    /// Writes the config to a file.
+    pub fn save<P: AsRef<Utf8Path>>(&self, path: P) -> Result<(), Error> {
+        std::fs::write(path.as_ref(), self.to_bytes()?)?;
+        
+        Ok(())
+    }
+    
+    /// Returns the contents of the config file, encoded for its format.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        self.format.encode(&self.to_string())
+    }
+    
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines.iter().find_map(|line| match line {
+            Line::Entry { key: other, value } if other == key => Some(value.as_str()),
+            _ => None,
+        })
+    }
+    
+    /// Sets a key's value, replacing it in place or appending it if it doesn't exist.
+    pub fn set<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        let (key, value) = (key.into(), value.into());
+        
+        for line in self.lines.iter_mut() {
+            if let Line::Entry { key: other, value: old } = line {
+                if *other == key {
+                    *old = value;
+                    return;
+                }
+            }
+        }
+        
+        self.lines.push(Line::Entry { key, value });
+    }
+    
+    /// Removes a key, returning its previous value.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let index = self.lines.iter().position(|line| matches!(line, Line::Entry { key: other, .. } if other == key))?;
+        
+        match self.lines.remove(index) {
+            Line::Entry { value, .. } => Some(value),
+            Line::Other(_) => None,
+        }
+    }
+    
+    /// Applies a typed setting.
+    /// 
+    /// Returns [`Error::UnsupportedSetting`] if the setting can't be stored in this config's format.
+    pub fn apply(&mut self, setting: &FceuxSetting) -> Result<(), Error> {
+        let (key, value) = setting.entry(self.format).ok_or_else(|| Error::UnsupportedSetting(format!("{setting:?}")))?;
+        self.set(key, value);
+        
+        Ok(())
+    }
+}
+impl Display for FceuxConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for line in &self.lines {
+            match (line, self.format) {
+                (Line::Entry { key, value }, FceuxConfigFormat::Sdl) => writeln!(f, "{key} = {value}")?,
+                (Line::Entry { key, value }, FceuxConfigFormat::Win32) if value.is_empty() => writeln!(f, "{key}")?,
+                (Line::Entry { key, value }, FceuxConfigFormat::Win32) => writeln!(f, "{key} {value}")?,
+                (Line::Other(text), _) => writeln!(f, "{text}")?,
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const SDL: &str = "# FCEUX config\nSDL.Sound = 1\nSDL.LastOpenFile = /home/user/roms/Super Mario Bros.nes\n\nSDL.PAL = 0\n";
+    const WIN32: &str = "sound 1\nnewppu 0\nlastrom C:\\roms\\Super Mario Bros.nes\nresetToMatch\n";
+    
+    #[test]
+    fn parses_sdl() {
+        let config = FceuxConfig::parse(SDL, FceuxConfigFormat::Sdl);
+        
+        assert_eq!(config.get("SDL.Sound"), Some("1"));
+        assert_eq!(config.get("SDL.LastOpenFile"), Some("/home/user/roms/Super Mario Bros.nes"));
+        assert_eq!(config.get("SDL.PAL"), Some("0"));
+        assert_eq!(config.get("# FCEUX config"), None);
+        assert_eq!(config.to_string(), SDL);
+    }
+    
+    #[test]
+    fn parses_win32() {
+        let config = FceuxConfig::parse(WIN32, FceuxConfigFormat::Win32);
+        
+        assert_eq!(config.get("sound"), Some("1"));
+        assert_eq!(config.get("lastrom"), Some("C:\\roms\\Super Mario Bros.nes"));
+        assert_eq!(config.get("resetToMatch"), Some(""));
+        assert_eq!(config.to_string(), WIN32);
+    }
+    
+    #[test]
+    fn edits_in_place() {
+        let mut config = FceuxConfig::parse(SDL, FceuxConfigFormat::Sdl);
+        config.apply(&FceuxSetting::Sound(false)).unwrap();
+        config.apply(&FceuxSetting::Input { port: 1, device: "GamePad".into() }).unwrap();
+        assert_eq!(config.remove("SDL.PAL"), Some("0".into()));
+        
+        assert_eq!(config.to_string(), "# FCEUX config\nSDL.Sound = 0\nSDL.LastOpenFile = /home/user/roms/Super Mario Bros.nes\n\nSDL.Input.1 = GamePad\n");
+        
+        let mut config = FceuxConfig::parse(WIN32, FceuxConfigFormat::Win32);
+        config.apply(&FceuxSetting::Region(FceuxRegion::Pal)).unwrap();
+        assert!(matches!(config.apply(&FceuxSetting::Input { port: 1, device: "GamePad".into() }), Err(Error::UnsupportedSetting(_))));
+        
+        assert_eq!(config.get("palyo"), Some("1"));
+        assert!(config.to_string().ends_with("resetToMatch\npalyo 1\n"));
+    }
+    
+    #[test]
+    fn round_trips_both_formats() {
+        for (text, format) in [(SDL, FceuxConfigFormat::Sdl), (WIN32, FceuxConfigFormat::Win32)] {
+            let config = FceuxConfig::parse(text, format);
+            let bytes = config.to_bytes().unwrap();
+            
+            assert_eq!(FceuxConfig::parse(&format.decode(&bytes).unwrap(), format), config);
+        }
+    }
+    
+    #[test]
+    fn keeps_ansi_bytes() {
+        // "lastrom C:\roms\Pokémon.nes" in Windows-1252, followed by a byte that isn't valid UTF-8 on its own
+        let bytes = b"lastrom C:\\roms\\Pok\xe9mon.nes\nlastmovie C:\\\x93quoted\x94.fm2\n".to_vec();
+        let dir = crate::includes::create_temp_dir("emu-runner-test").unwrap();
+        let path = dir.join("fceux.cfg");
+        std::fs::write(&path, &bytes).unwrap();
+        
+        let mut config = FceuxConfig::load(&path, FceuxConfigFormat::Win32).unwrap();
+        assert_eq!(config.to_bytes().unwrap(), bytes);
+        
+        config.set("sound", "0");
+        config.save(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), [bytes, b"sound 0\n".to_vec()].concat());
+        
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+    
+    #[test]
+    fn rejects_unencodable_text() {
+        assert!(FceuxConfigFormat::Sdl.decode(b"SDL.LastOpenFile = Pok\xe9mon.nes\n").is_err());
+        assert!(matches!(FceuxConfigFormat::Win32.encode("lastrom ポケモン.nes\n"), Err(Error::UnsupportedSetting(_))));
+        assert_eq!(FceuxConfigFormat::Sdl.encode("SDL.LastOpenFile = ポケモン.nes\n").unwrap(), "SDL.LastOpenFile = ポケモン.nes\n".as_bytes());
+    }
+}
@@ -1,6 +1,14 @@
-use camino::Utf8PathBuf;
-use crate::{EmulatorContext, Error};
-use crate::includes::copy_if_different;
+use camino::{Utf8Path, Utf8PathBuf};
+use crate::{CommonOptions, EmulatorContext, Error};
+use crate::doctor::Requirement;
+use crate::env::{Deterministic, EnvInheritance};
+use crate::manifest::Manifest;
+use crate::placement::{require_file, PlacedFiles, Placement, Placements};
+use crate::wine::{WineOptions, WinePrefix};
+
+pub mod config;
+
+use config::{GensConfig, GensSetting};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum GensVersion {
@@ -16,50 +24,62 @@ pub struct GensContext {
     pub rom: Option<Utf8PathBuf>,
     pub movie: Option<Utf8PathBuf>,
     pub lua: Option<Utf8PathBuf>,
+    
+    /// How the ROM, movie and Lua script are made available to the emulator.
+    /// 
+    /// Defaults to copying the ROM into the working directory, and passing the movie and Lua script as absolute paths.
+    pub placement: Placements,
+    
+    /// Base config, written to `Gens.cfg` in the working directory during `prepare`.
+    pub config: Option<Utf8PathBuf>,
+    
+    /// Settings applied on top of the base config.
+    /// 
+    /// The base config is [`config`](GensContext::config) if set, otherwise the existing `Gens.cfg`.
+    pub config_overrides: Vec<GensSetting>,
+    
+    /// Whether multiple instances may run from the working directory at once.
+    /// 
+    /// When true, [`run`](crate::run) holds a [shared](crate::lock::LockMode::Shared) lock on the working directory for the whole run,
+    /// instead of an exclusive one. `prepare` and `cleanup` still happen one run at a time, under a separate prepare lock.
+    pub concurrent_instances: bool,
+    
+    /// If set, the emulator is run with a [`Deterministic`] environment. Unless [`env_inheritance`](Self::env_inheritance)
+    /// is also set, only [essential](EnvInheritance::essential) variables are then inherited from the parent environment.
+    pub deterministic: Option<Deterministic>,
+    
+    /// Which variables are inherited from the parent environment.
+    /// 
+    /// If not set, only [essential](EnvInheritance::essential) variables are inherited when [`deterministic`](Self::deterministic)
+    /// is set, otherwise everything is inherited.
+    pub env_inheritance: Option<EnvInheritance>,
+    
+    /// Additional environment variables, applied after any set by emu-runner.
+    pub extra_env: Vec<(String, String)>,
+    
+    /// Additional arguments passed to the emulator before `-rom`. Paths are passed as-is, even when run through Wine.
+    pub extra_args: Vec<String>,
+    
+    /// Additional arguments passed to the emulator after all others (including the movie and Lua script).
+    pub trailing_args: Vec<String>,
+    
+    /// Options used when Gens is run through Wine.
+    pub wine: WineOptions,
     pub working_dir: Utf8PathBuf,
+    placed: PlacedFiles,
+    manifest: Manifest,
 }
 impl EmulatorContext for GensContext {
     fn cmd_name(&self) -> String {
         #[cfg(target_family = "unix")]
-        { "wine".into() }
+        { self.wine.loader_for(self.working_dir.join("Gens.exe")) }
         
         #[cfg(target_family = "windows")]
         { "Gens.exe".into() }
     }
     
     fn args(&self) -> Vec<String> {
-        let mut args = Vec::with_capacity(5);
-        
-        #[cfg(target_family = "unix")]
-        {
-            let mut executable = self.working_dir.clone();
-            executable.push("Gens.exe");
-            args.push(executable.to_string());
-        }
-        
-        use GensVersion::*;
-        match self.version {
-            Ver11A | Ver11B | GitA2425B5 => { // TODO: verify for correctness
-                if self.start_paused {
-                    args.push("-pause".into());
-                    args.push("0".into());
-                }
-                if let Some(rom) = self.rom.as_ref() {
-                    args.push("-rom".into());
-                    args.push(rom.to_string());
-                }
-                if let Some(movie) = self.movie.as_ref() {
-                    args.push("-play".into());
-                    args.push(movie.to_string());
-                }
-                if let Some(lua) = self.lua.as_ref() {
-                    args.push("-lua".into());
-                    args.push(lua.to_string());
-                }
-            },
-        }
-        
-        args
+        self.args_with(&self.placed)
     }
     
     fn env(&self) -> Vec<(String, String)> {
@@ -67,59 +87,29 @@ impl EmulatorContext for GensContext {
 
         #[cfg(target_family = "unix")]
         {
-            let mut prefix = self.working_dir.clone();
-            prefix.push(".wine/");
-            
-            vars.push(("WINEPREFIX".into(), prefix.to_string()));
+            vars.extend(self.wine_prefix().env());
+        }
+        if let Some(deterministic) = self.deterministic.as_ref() {
+            vars.extend(deterministic.vars());
         }
+        vars.extend(self.extra_env.iter().cloned());
         
         vars
     }
     
     fn prepare(&mut self) -> Result<(), Error> {
-        // Gens has inconsistent requirements for where files exist
+        // if this context was already prepared, start over from the original files
+        self.cleanup()?;
+        let launch = self.launch()?;
+        self.placed = launch.placed;
+        self.manifest = launch.manifest;
         
-        if let Some(rom) = self.rom.as_ref() {
-            if !rom.is_file() {
-                return Err(Error::MissingRom(rom.clone()));
-            }
-            let mut dest = self.working_dir.clone();
-            dest.push(rom.file_name().unwrap());
-            
-            copy_if_different(&std::fs::read(rom)?, dest)?;
-            
-            self.rom = Some(rom.file_name().unwrap().into());
-        }
-        if let Some(movie) = self.movie.as_ref() {
-            if !movie.is_file() {
-                return Err(Error::MissingMovie(movie.clone()));
-            }
-            
-            // movie path can be outside working dir, but must be absolute
-            if !movie.is_absolute() {
-                let mut dest = self.working_dir.clone();
-                dest.push(movie.file_name().unwrap());
-                
-                copy_if_different(&std::fs::read(movie)?, dest)?;
-                
-                self.movie = Some(movie.file_name().unwrap().into());
-            }
-        }
-        if let Some(lua) = self.lua.as_ref() {
-            if !lua.is_file() {
-                return Err(Error::MissingLua(lua.clone()));
-            }
-            
-            // lua path can be outside working dir, but must be absolute
-            if !lua.is_absolute() {
-                let mut dest = self.working_dir.clone();
-                dest.push(lua.file_name().unwrap());
-                
-                copy_if_different(&std::fs::read(lua)?, dest)?;
-                
-                self.lua = Some(lua.file_name().unwrap().into());
-            }
-        }
+        Ok(())
+    }
+    
+    fn cleanup(&mut self) -> Result<(), Error> {
+        self.manifest.restore()?;
+        self.placed = PlacedFiles::default();
         
         Ok(())
     }
@@ -127,6 +117,58 @@ impl EmulatorContext for GensContext {
     fn working_dir(&self) -> Utf8PathBuf {
         self.working_dir.clone()
     }
+    
+    fn set_working_dir(&mut self, working_dir: Utf8PathBuf) -> Result<(), Error> {
+        self.working_dir = working_dir;
+        
+        Ok(())
+    }
+    
+    fn supports_concurrent_instances(&self) -> bool {
+        self.concurrent_instances
+    }
+    
+    fn env_inheritance(&self) -> EnvInheritance {
+        match (self.env_inheritance.as_ref(), self.deterministic.as_ref()) {
+            (Some(inheritance), _) => inheritance.clone(),
+            (None, Some(_)) => EnvInheritance::essential(),
+            (None, None) => EnvInheritance::All,
+        }
+    }
+    
+    fn requirements(&self) -> Vec<Requirement> {
+        let executable = Requirement::Executable(self.working_dir.join("Gens.exe"));
+        
+        #[cfg(target_family = "unix")]
+        { vec![Requirement::Wine(self.cmd_name()), executable] }
+        
+        #[cfg(target_family = "windows")]
+        { vec![executable] }
+    }
+}
+impl CommonOptions for GensContext {
+    fn set_rom(&mut self, rom: Option<Utf8PathBuf>) {
+        self.rom = rom;
+    }
+    
+    fn set_movie(&mut self, movie: Option<Utf8PathBuf>) {
+        self.movie = movie;
+    }
+    
+    fn set_lua(&mut self, lua: Option<Utf8PathBuf>) {
+        self.lua = lua;
+    }
+    
+    /// Sets the base config used for `Gens.cfg`. The original `Gens.cfg` is restored once the run has finished.
+    fn set_config(&mut self, config: Option<Utf8PathBuf>) {
+        self.config = config;
+    }
+    
+    fn set_pause(&mut self, start_paused: bool) -> Result<(), Error> {
+        self.start_paused = start_paused;
+        
+        Ok(())
+    }
 }
 impl GensContext {
     /// Creates a new Context with default options.
@@ -153,7 +195,19 @@ impl GensContext {
             rom: None,
             movie: None,
             lua: None,
+            placement: Placements::default().with_rom(Placement::Copy),
+            config: None,
+            config_overrides: vec![],
+            concurrent_instances: false,
+            deterministic: None,
+            env_inheritance: None,
+            extra_env: vec![],
+            extra_args: vec![],
+            trailing_args: vec![],
+            wine: WineOptions::default(),
             working_dir,
+            placed: PlacedFiles::default(),
+            manifest: Manifest::new(),
         })
     }
     
@@ -164,24 +218,251 @@ impl GensContext {
         }
     }
     
+    /// Same as [`CommonOptions::with_rom`], usable without importing the trait.
     pub fn with_rom<P: Into<Utf8PathBuf>>(self, rom: P) -> Self {
+        CommonOptions::with_rom(self, rom)
+    }
+    
+    /// Same as [`CommonOptions::with_movie`], usable without importing the trait.
+    pub fn with_movie<P: Into<Utf8PathBuf>>(self, movie: P) -> Self {
+        CommonOptions::with_movie(self, movie)
+    }
+    
+    /// Same as [`CommonOptions::with_lua`], usable without importing the trait.
+    pub fn with_lua<P: Into<Utf8PathBuf>>(self, lua: P) -> Self {
+        CommonOptions::with_lua(self, lua)
+    }
+    
+    /// Same as [`CommonOptions::with_config`], usable without importing the trait.
+    pub fn with_config<P: Into<Utf8PathBuf>>(self, config: P) -> Self {
+        CommonOptions::with_config(self, config)
+    }
+    
+    /// Sets how the ROM, movie and Lua script are made available to the emulator.
+    pub fn with_placement(self, placement: Placements) -> Self {
         Self {
-            rom: Some(rom.into()),
+            placement,
             ..self
         }
     }
     
-    pub fn with_movie<P: Into<Utf8PathBuf>>(self, movie: P) -> Self {
+    /// Adds a setting to apply on top of the base config.
+    pub fn with_config_override(mut self, setting: GensSetting) -> Self {
+        self.config_overrides.push(setting);
+        self
+    }
+    
+    /// Sets whether multiple instances may run from the working directory at once.
+    pub fn with_concurrent_instances(self, concurrent_instances: bool) -> Self {
         Self {
-            movie: Some(movie.into()),
+            concurrent_instances,
             ..self
         }
     }
     
-    pub fn with_lua<P: Into<Utf8PathBuf>>(self, lua: P) -> Self {
+    /// Runs the emulator with the given environment preset, to make runs reproducible across machines.
+    pub fn with_deterministic(self, deterministic: Deterministic) -> Self {
         Self {
-            lua: Some(lua.into()),
+            deterministic: Some(deterministic),
             ..self
         }
     }
+    
+    pub fn with_env_inheritance(self, env_inheritance: EnvInheritance) -> Self {
+        Self {
+            env_inheritance: Some(env_inheritance),
+            ..self
+        }
+    }
+    
+    /// Adds an environment variable, overriding any set by emu-runner.
+    pub fn with_env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.extra_env.push((key.into(), value.into()));
+        self
+    }
+    
+    /// Adds an argument passed to the emulator before the ROM.
+    pub fn with_extra_arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.extra_args.push(arg.into());
+        self
+    }
+    
+    /// Adds an argument passed to the emulator after all others.
+    pub fn with_trailing_arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.trailing_args.push(arg.into());
+        self
+    }
+    
+    pub fn with_wine(self, wine: WineOptions) -> Self {
+        Self {
+            wine,
+            ..self
+        }
+    }
+    
+    /// Maps a drive letter to a host directory for paths passed to Wine.
+    pub fn with_wine_drive<P: Into<Utf8PathBuf>>(self, letter: char, dir: P) -> Self {
+        Self {
+            wine: self.wine.with_drive(letter, dir),
+            ..self
+        }
+    }
+    
+    /// Places the input files and writes `Gens.cfg` into the working directory, without modifying the context.
+    /// 
+    /// This is what `prepare` does. The returned plan holds the [arguments](GensLaunch::args) to run the emulator with,
+    /// and records every change, which are undone by [`GensLaunch::cleanup`];
+    /// if preparation fails part way through, changes made so far are undone before returning.
+    pub fn launch(&self) -> Result<GensLaunch, Error> {
+        let mut launch = GensLaunch {
+            placed: PlacedFiles::default(),
+            manifest: Manifest::new(),
+        };
+        if let Err(err) = self.populate_launch(&mut launch) {
+            let _ = launch.cleanup();
+            return Err(err);
+        }
+        
+        Ok(launch)
+    }
+    
+    /// Returns the files written into the emulator's directory by `prepare`, which are restored during `cleanup`.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+    
+    /// Returns the paths of the ROM, movie and Lua script passed to the emulator by the current run.
+    /// 
+    /// Empty until the context has been prepared.
+    pub fn placed(&self) -> &PlacedFiles {
+        &self.placed
+    }
+    
+    /// Returns the Wine prefix used to run Gens.
+    pub fn wine_prefix(&self) -> WinePrefix {
+        let mut prefix = self.wine.prefix_for(&self.working_dir, self.working_dir.join("Gens.exe"));
+        if let Some(deterministic) = self.deterministic.as_ref() {
+            prefix.dll_overrides.extend(deterministic.wine_dll_overrides());
+        }
+        
+        prefix
+    }
+    
+    fn populate_launch(&self, launch: &mut GensLaunch) -> Result<(), Error> {
+        // Gens has inconsistent requirements for where files exist,
+        // which is why the rom is copied into the working dir by default (see `GensContext::new`)
+        
+        launch.placed = self.placement.place_inputs(self.rom.as_ref(), self.movie.as_ref(), self.lua.as_ref(), &self.working_dir, &mut launch.manifest)?;
+        
+        if let Some(config) = self.config.as_ref() {
+            require_file(config, Error::MissingConfig)?;
+        }
+        if self.config.is_some() || !self.config_overrides.is_empty() {
+            let dest = self.working_dir.join("Gens.cfg");
+            let mut config = match self.config.as_ref() {
+                Some(config) => GensConfig::load(config)?,
+                None if dest.is_file() => GensConfig::load(&dest)?,
+                None => GensConfig::new(),
+            };
+            for setting in &self.config_overrides {
+                match setting {
+                    GensSetting::Path { kind, path } => config.apply(&GensSetting::Path { kind: *kind, path: self.arg_path(path).into() }),
+                    setting => config.apply(setting),
+                }
+            }
+            
+            launch.manifest.write(&config.to_bytes()?, dest)?;
+        }
+        
+        #[cfg(target_family = "unix")]
+        {
+            self.wine.prepare_prefix(&self.wine_prefix())?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Returns the emulator's arguments, passing the given placed files in place of the ROM, movie and Lua paths.
+    fn args_with(&self, placed: &PlacedFiles) -> Vec<String> {
+        let mut args = Vec::with_capacity(5);
+        
+        #[cfg(target_family = "unix")]
+        {
+            if let Some(deterministic) = self.deterministic.as_ref() {
+                args.extend(deterministic.wine_desktop_args());
+            }
+            let mut executable = self.working_dir.clone();
+            executable.push("Gens.exe");
+            args.push(executable.to_string());
+        }
+        
+        use GensVersion::*;
+        match self.version {
+            Ver11A | Ver11B | GitA2425B5 => { // TODO: verify for correctness
+                if self.start_paused {
+                    args.push("-pause".into());
+                    args.push("0".into());
+                }
+                args.extend(self.extra_args.iter().cloned());
+                if let Some(rom) = placed.rom.as_ref().or(self.rom.as_ref()) {
+                    args.push("-rom".into());
+                    args.push(self.arg_path(rom));
+                }
+                if let Some(movie) = placed.movie.as_ref().or(self.movie.as_ref()) {
+                    args.push("-play".into());
+                    args.push(self.arg_path(movie));
+                }
+                if let Some(lua) = placed.lua.as_ref().or(self.lua.as_ref()) {
+                    args.push("-lua".into());
+                    args.push(self.arg_path(lua));
+                }
+                args.extend(self.trailing_args.iter().cloned());
+            },
+        }
+        
+        args
+    }
+    
+    /// Formats a file path for the emulator's command line, translating it to a Windows path when run through Wine.
+    fn arg_path(&self, path: &Utf8Path) -> String {
+        #[cfg(target_family = "unix")]
+        { self.wine.to_windows_path(path) }
+        
+        #[cfg(target_family = "windows")]
+        { path.to_string() }
+    }
+}
+/// Changes made to the emulator's directory by [`GensContext::launch`], undone by [`cleanup`](GensLaunch::cleanup).
+#[derive(Debug)]
+pub struct GensLaunch {
+    placed: PlacedFiles,
+    manifest: Manifest,
+}
+impl GensLaunch {
+    /// Returns the arguments to run `ctx`'s emulator with, using the placed files.
+    pub fn args(&self, ctx: &GensContext) -> Vec<String> {
+        ctx.args_with(&self.placed)
+    }
+    
+    /// Builds the [`Command`](std::process::Command) to run `ctx`'s emulator with, using the placed files.
+    pub fn command(&self, ctx: &GensContext) -> std::process::Command {
+        crate::command_with_args(ctx, self.args(ctx))
+    }
+    
+    /// Returns the paths of the ROM, movie and Lua script passed to the emulator.
+    pub fn placed(&self) -> &PlacedFiles {
+        &self.placed
+    }
+    
+    /// Returns the files written into the emulator's directory, which are restored by [`cleanup`](GensLaunch::cleanup).
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+    
+    /// Restores every file written into the emulator's directory.
+    pub fn cleanup(mut self) -> Result<(), Error> {
+        self.manifest.restore()?;
+        
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -0,0 +1,295 @@
+use std::fmt::{Display, Formatter};
+use camino::{Utf8Path, Utf8PathBuf};
+use crate::Error;
+
+/// Console region emulated by Gens, stored as `Country` in the `[CPU]` section.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GensRegion {
+    Auto,
+    JapanNtsc,
+    Usa,
+    Europe,
+    JapanPal,
+}
+impl GensRegion {
+    fn value(&self) -> i32 {
+        match self {
+            GensRegion::Auto => -1,
+            GensRegion::JapanNtsc => 0,
+            GensRegion::Usa => 1,
+            GensRegion::Europe => 2,
+            GensRegion::JapanPal => 3,
+        }
+    }
+}
+
+/// Directory settings stored in the `[General]` section.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GensPath {
+    Rom,
+    Save,
+    Sram,
+    Movie,
+    Screenshot,
+}
+impl GensPath {
+    fn key(&self) -> &'static str {
+        match self {
+            GensPath::Rom => "Rom path",
+            GensPath::Save => "Save path",
+            GensPath::Sram => "SRAM path",
+            GensPath::Movie => "Movie path",
+            GensPath::Screenshot => "Screen Shot path",
+        }
+    }
+}
+
+/// A single typed change to `Gens.cfg`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GensSetting {
+    /// Enables or disables sound output.
+    Sound(bool),
+    /// Number of frames skipped between rendered frames, or `-1` for automatic.
+    FrameSkip(i32),
+    /// Selects the emulated region.
+    Region(GensRegion),
+    /// Sets a directory. Contexts translate it to a Windows path when Gens is run through Wine.
+    Path { kind: GensPath, path: Utf8PathBuf },
+    /// Any other key, written as-is.
+    Raw { section: String, key: String, value: String },
+}
+impl GensSetting {
+    /// Returns the section, key, and value this setting is stored as.
+    pub fn entry(&self) -> (String, String, String) {
+        let (section, key, value) = match self {
+            GensSetting::Sound(enabled) => ("Sound", "State", if *enabled { "1".into() } else { "0".into() }),
+            GensSetting::FrameSkip(frames) => ("Graphics", "Frame skip", frames.to_string()),
+            GensSetting::Region(region) => ("CPU", "Country", region.value().to_string()),
+            GensSetting::Path { kind, path } => ("General", kind.key(), path.to_string()),
+            GensSetting::Raw { section, key, value } => return (section.clone(), key.clone(), value.clone()),
+        };
+        
+        (section.into(), key.into(), value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    Section(String),
+    Entry { key: String, value: String },
+    Other(String),
+}
+
+/// An INI-style `Gens.cfg` file which can be edited key by key.
+/// 
+/// Comments, unknown keys, and ordering are preserved.
+/// 
+/// Gens writes the file in the system's ANSI code page, so each byte is read as the character with the same value
+/// (Latin-1), which keeps any code page intact when the file is written back.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct GensConfig {
+    lines: Vec<Line>,
+}
+impl GensConfig {
+    /// Creates an empty config. Gens uses defaults for every missing key.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn parse(text: &str) -> Self {
+        let lines = text.lines().map(|line| {
+            let trimmed = line.trim();
+            
+            if let Some(section) = trimmed.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                return Line::Section(section.to_string());
+            }
+            if trimmed.starts_with(';') {
+                return Line::Other(line.to_string());
+            }
+            
+            match trimmed.split_once('=') {
+                Some((key, value)) => Line::Entry { key: key.trim().to_string(), value: value.trim().to_string() },
+                None => Line::Other(line.to_string()),
+            }
+        }).collect();
+        
+        Self { lines }
+    }
+    
+    /// Reads and parses a config file.
+    pub fn load<P: AsRef<Utf8Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        if !path.is_file() {
+            return Err(Error::MissingConfig(path.to_path_buf()));
+        }
+        
+        Ok(Self::parse(&std::fs::read(path)?.iter().map(|byte| *byte as char).collect::<String>()))
+    }
+    
+    /// Writes the config to a file.
+    pub fn save<P: AsRef<Utf8Path>>(&self, path: P) -> Result<(), Error> {
+        std::fs::write(path.as_ref(), self.to_bytes()?)?;
+        
+        Ok(())
+    }
+    
+    /// Returns the contents of the config file, one byte per character.
+    /// 
+    /// Returns [`Error::UnsupportedSetting`] if the config contains a character outside of Latin-1.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        self.to_string().chars()
+            .map(|c| u8::try_from(c).map_err(|_| Error::UnsupportedSetting(format!("character {c:?} in Gens.cfg"))))
+            .collect()
+    }
+    
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        let range = self.section_range(section)?;
+        
+        self.lines[range].iter().find_map(|line| match line {
+            Line::Entry { key: other, value } if other.eq_ignore_ascii_case(key) => Some(value.as_str()),
+            _ => None,
+        })
+    }
+    
+    /// Sets a key's value, replacing it in place, or appending it to the section (which is created if needed).
+    pub fn set<S: Into<String>, K: Into<String>, V: Into<String>>(&mut self, section: S, key: K, value: V) {
+        let (section, key, value) = (section.into(), key.into(), value.into());
+        
+        let range = match self.section_range(&section) {
+            Some(range) => range,
+            None => {
+                if matches!(self.lines.last(), Some(line) if *line != Line::Other("".into())) {
+                    self.lines.push(Line::Other("".into()));
+                }
+                self.lines.push(Line::Section(section.clone()));
+                self.lines.len()..self.lines.len()
+            },
+        };
+        
+        for line in self.lines[range.clone()].iter_mut() {
+            if let Line::Entry { key: other, value: old } = line {
+                if other.eq_ignore_ascii_case(&key) {
+                    *old = value;
+                    return;
+                }
+            }
+        }
+        
+        // insert after the last entry, so trailing blank lines stay between sections
+        let index = self.lines[range.clone()].iter()
+            .rposition(|line| !matches!(line, Line::Other(text) if text.trim().is_empty()))
+            .map(|pos| range.start + pos + 1)
+            .unwrap_or(range.start);
+        self.lines.insert(index, Line::Entry { key, value });
+    }
+    
+    /// Applies a typed setting.
+    pub fn apply(&mut self, setting: &GensSetting) {
+        let (section, key, value) = setting.entry();
+        self.set(section, key, value);
+    }
+    
+    /// Returns the range of lines following a section header, up to the next section.
+    fn section_range(&self, section: &str) -> Option<std::ops::Range<usize>> {
+        let start = self.lines.iter().position(|line| matches!(line, Line::Section(name) if name.eq_ignore_ascii_case(section)))? + 1;
+        let end = self.lines[start..].iter()
+            .position(|line| matches!(line, Line::Section(_)))
+            .map(|pos| start + pos)
+            .unwrap_or(self.lines.len());
+        
+        Some(start..end)
+    }
+}
+impl Display for GensConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for line in &self.lines {
+            match line {
+                Line::Section(name) => writeln!(f, "[{name}]")?,
+                Line::Entry { key, value } => writeln!(f, "{key}={value}")?,
+                Line::Other(text) => writeln!(f, "{text}")?,
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const CONFIG: &str = "[General]\nRom path=C:\\roms\n\n[Sound]\n; 1 = enabled\nState=1\nRate=44100\n\n";
+    
+    #[test]
+    fn set_creates_missing_section() {
+        let mut config = GensConfig::new();
+        config.set("Sound", "State", "0");
+        assert_eq!(config.to_string(), "[Sound]\nState=0\n");
+        
+        config.set("CPU", "Country", "1");
+        assert_eq!(config.to_string(), "[Sound]\nState=0\n\n[CPU]\nCountry=1\n");
+        
+        let mut config = GensConfig::parse(CONFIG);
+        config.apply(&GensSetting::FrameSkip(-1));
+        assert_eq!(config.to_string(), format!("{CONFIG}[Graphics]\nFrame skip=-1\n"));
+    }
+    
+    #[test]
+    fn set_replaces_existing_key() {
+        let mut config = GensConfig::parse(CONFIG);
+        config.apply(&GensSetting::Sound(false));
+        config.set("general", "ROM PATH", "Z:\\roms");
+        
+        assert_eq!(config.get("Sound", "State"), Some("0"));
+        assert_eq!(config.get("General", "Rom path"), Some("Z:\\roms"));
+        assert_eq!(config.to_string(), CONFIG.replace("State=1", "State=0").replace("C:\\roms", "Z:\\roms"));
+    }
+    
+    #[test]
+    fn set_appends_before_trailing_blank_lines() {
+        let mut config = GensConfig::parse(CONFIG);
+        config.apply(&GensSetting::Path { kind: GensPath::Movie, path: "Z:\\movies".into() });
+        config.set("Sound", "Stereo", "1");
+        
+        assert_eq!(config.to_string(), "[General]\nRom path=C:\\roms\nMovie path=Z:\\movies\n\n[Sound]\n; 1 = enabled\nState=1\nRate=44100\nStereo=1\n\n");
+    }
+    
+    #[test]
+    fn set_fills_empty_section() {
+        let mut config = GensConfig::parse("[CPU]\n\n[Sound]\nState=1\n");
+        config.apply(&GensSetting::Region(GensRegion::Europe));
+        
+        assert_eq!(config.to_string(), "[CPU]\nCountry=2\n\n[Sound]\nState=1\n");
+    }
+    
+    #[test]
+    fn parse_preserves_comments_and_order() {
+        let config = GensConfig::parse(CONFIG);
+        
+        assert_eq!(config.get("Sound", "Rate"), Some("44100"));
+        assert_eq!(config.get("Sound", "; 1 "), None);
+        assert_eq!(config.get("Graphics", "Frame skip"), None);
+        assert_eq!(config.to_string(), CONFIG);
+    }
+    
+    #[test]
+    fn keeps_ansi_bytes() {
+        // "Rom path=C:\roms\Pokémon" in Windows-1252, followed by bytes that aren't valid UTF-8 on their own
+        let bytes = b"[General]\nRom path=C:\\roms\\Pok\xe9mon\nMovie path=C:\\\x93quoted\x94\n".to_vec();
+        let dir = crate::includes::create_temp_dir("emu-runner-test").unwrap();
+        let path = dir.join("Gens.cfg");
+        std::fs::write(&path, &bytes).unwrap();
+        
+        let mut config = GensConfig::load(&path).unwrap();
+        assert_eq!(config.to_bytes().unwrap(), bytes);
+        
+        config.apply(&GensSetting::Sound(false));
+        config.save(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), [bytes, b"\n[Sound]\nState=0\n".to_vec()].concat());
+        
+        config.set("General", "Rom path", "C:\\roms\\ポケモン");
+        assert!(matches!(config.to_bytes(), Err(Error::UnsupportedSetting(_))));
+        
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
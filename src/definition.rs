@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Deserialize;
+use crate::Error;
+use crate::contexts::CustomContext;
+use crate::contexts::custom::Launcher;
+use crate::includes::hash_file;
+use crate::placement::Placements;
+
+/// Declarative description of an emulator, loaded from TOML at runtime and run as a [`CustomContext`].
+/// 
+/// ```toml
+/// name = "snes9x"
+/// 
+/// # executable names, in order of preference
+/// executables = ["snes9x-gtk", "snes9x-x64.exe"]
+/// 
+/// # argument templates used by any version without its own
+/// args = ["-playmovie {movie}", "{rom}"]
+/// 
+/// # files which must exist in the working directory
+/// required_files = ["snes9x.conf"]
+/// 
+/// # how each executable is launched (otherwise `.exe` uses wine, `.sh` uses bash, and anything else is run natively)
+/// [launchers]
+/// "start.sh" = "bash"
+/// 
+/// # SHA1 of the executable, mapped to a version name
+/// [versions]
+/// "2c1a4b8c8d1c9f3e7ab4d4c41d03c1b4c6bd9d3f" = "1.60"
+/// 
+/// # argument templates for specific versions
+/// [version_args]
+/// "1.60" = ["-movieplay {movie}", "{rom}"]
+/// 
+/// [env]
+/// SDL_AUDIODRIVER = "dummy"
+/// 
+/// [placement]
+/// rom = "copy"
+/// 
+/// # files copied into the working directory before each run, relative to the definition file
+/// [[files]]
+/// src = "configs/snes9x.conf"
+/// dest = "snes9x.conf"
+/// ```
+/// 
+/// Argument templates use the same placeholders as [`CustomContext`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmulatorDefinition {
+    /// Name of the emulator.
+    pub name: String,
+    
+    /// Executable names looked for in the working directory, in order of preference.
+    pub executables: Vec<String>,
+    
+    /// Launcher used for specific executables, overriding the one inferred from the file extension.
+    #[serde(default)]
+    pub launchers: BTreeMap<String, Launcher>,
+    
+    /// Versions of the emulator, keyed by the SHA1 checksum of the executable.
+    #[serde(default)]
+    pub versions: BTreeMap<String, String>,
+    
+    /// Argument templates used when the version is unknown, or has no templates of its own.
+    #[serde(default)]
+    pub args: Vec<String>,
+    
+    /// Argument templates for specific versions, keyed by version name.
+    #[serde(default)]
+    pub version_args: BTreeMap<String, Vec<String>>,
+    
+    /// Environment variables passed to the emulator. Values may contain placeholders.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    
+    /// Files which must exist in the working directory before the emulator is run.
+    #[serde(default)]
+    pub required_files: Vec<Utf8PathBuf>,
+    
+    /// Files copied into the working directory during `prepare`.
+    #[serde(default)]
+    pub files: Vec<DefinitionFile>,
+    
+    /// How the ROM, movie and Lua script are made available to the emulator.
+    #[serde(default)]
+    pub placement: Placements,
+    
+    /// Whether multiple instances may run from the working directory at once.
+    #[serde(default)]
+    pub concurrent_instances: bool,
+}
+
+/// A file copied into the working directory by an [`EmulatorDefinition`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DefinitionFile {
+    /// Source file. Relative paths are resolved against the definition file's directory by [`EmulatorDefinition::load`].
+    pub src: Utf8PathBuf,
+    
+    /// Destination, relative to the working directory.
+    pub dest: Utf8PathBuf,
+}
+
+impl EmulatorDefinition {
+    /// Parses a definition from a TOML string. Relative file sources are left as-is.
+    pub fn parse(toml: &str) -> Result<Self, Error> {
+        Ok(toml::from_str(toml)?)
+    }
+    
+    /// Loads a definition from a TOML file.
+    pub fn load<P: AsRef<Utf8Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut definition = Self::parse(&std::fs::read_to_string(path)?)?;
+        
+        if let Some(dir) = path.parent() {
+            for file in &mut definition.files {
+                file.src = dir.join(&file.src);
+            }
+        }
+        
+        Ok(definition)
+    }
+    
+    /// Loads every `.toml` file in a directory, sorted by file name.
+    pub fn load_dir<P: AsRef<Utf8Path>>(dir: P) -> Result<Vec<Self>, Error> {
+        let mut paths = vec![];
+        for entry in dir.as_ref().read_dir_utf8()? {
+            let path = entry?.into_path();
+            if path.extension() == Some("toml") && path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        
+        paths.iter().map(Self::load).collect()
+    }
+    
+    /// Returns the first of the [`executables`](Self::executables) which exists in the working directory.
+    pub fn determine_executable<P: AsRef<Utf8Path>>(&self, working_dir: P) -> Option<String> {
+        self.executables.iter().find(|exe| working_dir.as_ref().join(exe).is_file()).cloned()
+    }
+    
+    /// Returns the launcher used for the given executable.
+    pub fn launcher_for(&self, executable: &str) -> Launcher {
+        if let Some(launcher) = self.launchers.get(executable) {
+            return *launcher;
+        }
+        
+        match Utf8Path::new(executable).extension().map(|ext| ext.to_ascii_lowercase()).as_deref() {
+            Some("exe") => Launcher::Wine,
+            Some("sh") => Launcher::Bash,
+            _ => Launcher::Native,
+        }
+    }
+    
+    /// Determines the emulator version by comparing the SHA1 checksum of the executable with [`versions`](Self::versions).
+    pub fn detect_version<P: AsRef<Utf8Path>>(&self, working_dir: P) -> Option<String> {
+        let working_dir = working_dir.as_ref();
+        let exe = self.determine_executable(working_dir)?;
+        let sha1 = hash_file(working_dir.join(exe)).ok()?.to_string().to_lowercase();
+        
+        self.versions.iter()
+            .find(|(hash, _)| hash.to_lowercase() == sha1)
+            .map(|(_, version)| version.clone())
+    }
+    
+    /// Returns the argument templates used for the given version.
+    pub fn args_for(&self, version: Option<&str>) -> &[String] {
+        version.and_then(|version| self.version_args.get(version)).unwrap_or(&self.args)
+    }
+    
+    /// Creates a context for the emulator installed in the given directory, with default options.
+    /// 
+    /// If none of the executables exist in the directory (or the directory of the given file),
+    /// an error message will be returned.
+    pub fn context<P: Into<Utf8PathBuf>>(&self, working_dir: P) -> Result<CustomContext, Error> {
+        let mut working_dir = working_dir.into();
+        if working_dir.is_file() {
+            working_dir.pop();
+        }
+        
+        let Some(exe) = self.determine_executable(&working_dir) else {
+            let name = self.executables.first().map(|exe| exe.as_str()).unwrap_or(&self.name);
+            return Err(Error::MissingExecutable(working_dir.join(name)));
+        };
+        let version = self.detect_version(&working_dir);
+        
+        let mut ctx = CustomContext::new(working_dir, &exe)?
+            .with_launcher(self.launcher_for(&exe))
+            .with_placement(self.placement)
+            .with_concurrent_instances(self.concurrent_instances);
+        for template in self.args_for(version.as_deref()) {
+            ctx = ctx.with_arg_template(template);
+        }
+        for (key, value) in &self.env {
+            ctx = ctx.with_env(key, value);
+        }
+        for file in &self.required_files {
+            ctx = ctx.with_required_file(file);
+        }
+        for file in &self.files {
+            ctx = ctx.with_file(&file.src, &file.dest);
+        }
+        
+        Ok(ctx)
+    }
+}
\ No newline at end of file
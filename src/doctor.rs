@@ -0,0 +1,176 @@
+use std::process::{Command, Stdio};
+use camino::{Utf8Path, Utf8PathBuf};
+use crate::Error;
+
+/// A host program that must be available before an emulator can be run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Requirement {
+    /// `bash`, used to launch BizHawk's start script on unix.
+    Bash,
+    /// The Wine loader (`wine` or `wine64`) used to launch a Windows executable on unix.
+    Wine(String),
+    /// `mono`, used by BizHawk's start script on unix.
+    Mono,
+    /// `Xvfb`, the virtual X server used by [`Headless`](crate::headless::Headless).
+    Xvfb,
+    /// An emulator binary which must exist and be executable.
+    /// 
+    /// Windows programs only need to exist on unix, since they're started by Wine or mono.
+    Executable(Utf8PathBuf),
+    /// Any other program which must be on `PATH` (e.g. the helper of a [`Wrapped`](crate::wrapper::Wrapped) context).
+    Program(String),
+}
+impl Requirement {
+    /// Program names that satisfy this requirement, in order of preference.
+    pub fn candidates(&self) -> Vec<String> {
+        match self {
+            Requirement::Bash => vec!["bash".into()],
+            Requirement::Wine(loader) => vec![loader.clone()],
+            Requirement::Mono => vec!["mono".into()],
+            Requirement::Xvfb => vec!["Xvfb".into()],
+            Requirement::Executable(path) => vec![path.to_string()],
+            Requirement::Program(name) => vec![name.clone()],
+        }
+    }
+    
+    /// Converts an unsatisfied requirement into the matching [`Error`].
+    fn to_error(&self, found: Option<&Utf8Path>) -> Error {
+        match self {
+            Requirement::Bash => Error::MissingBash("bash".into()),
+            Requirement::Wine(loader) => Error::MissingWine(loader.into()),
+            Requirement::Mono => Error::MissingMono("mono".into()),
+            Requirement::Xvfb => Error::MissingXvfb("Xvfb".into()),
+            Requirement::Executable(path) => match found {
+                Some(_) => Error::NotExecutable(path.clone()),
+                None => Error::MissingExecutable(path.clone()),
+            },
+            Requirement::Program(name) => match found {
+                Some(path) => Error::NotExecutable(path.to_path_buf()),
+                None => Error::MissingExecutable(name.into()),
+            },
+        }
+    }
+}
+
+/// Result of checking a single [`Requirement`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub requirement: Requirement,
+    /// Location of the program, if one was found.
+    pub path: Option<Utf8PathBuf>,
+    /// Whether the program found at `path` can be executed.
+    pub executable: bool,
+    /// First line of the program's `--version` output, if available.
+    pub version: Option<String>,
+}
+impl Check {
+    pub fn is_ok(&self) -> bool {
+        self.path.is_some() && self.executable
+    }
+}
+
+/// Structured result of a preflight check, as returned by [`crate::doctor()`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+impl Report {
+    /// Returns true if every requirement was satisfied.
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|check| check.is_ok())
+    }
+    
+    /// Returns the checks which failed.
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|check| !check.is_ok())
+    }
+    
+    /// Returns the report if every requirement was satisfied, otherwise the error of the first failure.
+    pub fn into_result(self) -> Result<Self, Error> {
+        if let Some(check) = self.failures().next() {
+            return Err(check.requirement.to_error(check.path.as_deref()));
+        }
+        
+        Ok(self)
+    }
+}
+
+/// Checks each requirement against the host system.
+pub fn check(requirements: &[Requirement]) -> Report {
+    let checks = requirements.iter().map(|requirement| {
+        let path = match requirement {
+            Requirement::Executable(path) => Some(path.clone()).filter(|path| path.is_file()),
+            _ => requirement.candidates().iter().find_map(|name| find_program(name)),
+        };
+        let executable = path.as_deref().map(is_executable).unwrap_or(false);
+        
+        // Emulator binaries may open a window when asked for their version, so only query known host tools.
+        let version = match (requirement, path.as_ref()) {
+            (Requirement::Executable(_) | Requirement::Program(_), _) => None,
+            (Requirement::Xvfb, Some(path)) if executable => program_version(path, "-version"),
+            (_, Some(path)) if executable => program_version(path, "--version"),
+            _ => None,
+        };
+        
+        Check {
+            requirement: requirement.clone(),
+            path,
+            executable,
+            version,
+        }
+    }).collect();
+    
+    Report { checks }
+}
+
+/// Searches the `PATH` environment variable for a program with the given name.
+pub fn find_program(name: &str) -> Option<Utf8PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    
+    for dir in std::env::split_paths(&paths) {
+        let Ok(dir) = Utf8PathBuf::try_from(dir) else { continue };
+        
+        let path = dir.join(name);
+        if path.is_file() {
+            return Some(path);
+        }
+        
+        #[cfg(target_family = "windows")]
+        {
+            let path = path.with_extension("exe");
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+    }
+    
+    None
+}
+
+fn is_executable(path: &Utf8Path) -> bool {
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        
+        path.metadata().map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0).unwrap_or(false)
+            || crate::pe::read_machine(path).is_ok()
+    }
+    
+    #[cfg(target_family = "windows")]
+    { path.is_file() }
+}
+
+fn program_version(path: &Utf8Path, flag: &str) -> Option<String> {
+    let output = Command::new(path)
+        .arg(flag)
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    
+    let text = if output.stdout.is_empty() { output.stderr } else { output.stdout };
+    
+    String::from_utf8_lossy(&text).lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty())
+        .map(|line| line.to_string())
+}
\ No newline at end of file
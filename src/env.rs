@@ -0,0 +1,141 @@
+/// Variables passed through from the parent environment by [`EnvInheritance::essential`].
+/// 
+/// These are needed to find programs and connect to the display, but don't affect emulation.
+pub const ESSENTIAL_VARS: &[&str] = &["PATH", "HOME", "USER", "LOGNAME", "DISPLAY", "XAUTHORITY", "WAYLAND_DISPLAY", "XDG_RUNTIME_DIR"];
+
+/// Which variables the emulator inherits from the parent process's environment, before [`env`](crate::EmulatorContext::env) is applied.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum EnvInheritance {
+    /// The whole environment is inherited.
+    #[default]
+    All,
+    /// Only the listed variables are inherited, if they're set.
+    Allowlist(Vec<String>),
+    /// Nothing is inherited.
+    /// 
+    /// **Note:** Without `PATH`, helper programs such as `bash` or `wine` may not be found by the emulator.
+    Clean,
+}
+impl EnvInheritance {
+    /// Inherits only [`ESSENTIAL_VARS`].
+    pub fn essential() -> Self {
+        Self::Allowlist(ESSENTIAL_VARS.iter().map(|key| key.to_string()).collect())
+    }
+    
+    /// Returns the inherited variables which are set in the current environment,
+    /// or `None` if the whole environment is inherited.
+    pub fn inherited_vars(&self) -> Option<Vec<(String, String)>> {
+        match self {
+            EnvInheritance::All => None,
+            EnvInheritance::Allowlist(keys) => Some(keys.iter()
+                .filter_map(|key| std::env::var(key).ok().map(|value| (key.clone(), value)))
+                .collect()),
+            EnvInheritance::Clean => Some(vec![]),
+        }
+    }
+}
+
+/// Wine's audio drivers, which are disabled when audio is turned off.
+const WINE_AUDIO_DRIVERS: &str = "winealsa.drv,winepulse.drv,wineoss.drv,winecoreaudio.drv";
+
+/// Environment preset used to make runs reproducible across machines.
+/// 
+/// Fixes the time zone and locale, turns audio output off, and pins the display size. Unless set otherwise,
+/// contexts using this preset only inherit [`ESSENTIAL_VARS`] from the parent environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deterministic {
+    /// Value of `TZ`. Defaults to `UTC`.
+    pub timezone: String,
+    
+    /// Value of `LANG` and `LC_ALL`. Defaults to `C.UTF-8`.
+    pub locale: String,
+    
+    /// Whether audio output is left enabled. Defaults to false.
+    pub audio: bool,
+    
+    /// Size of the display, in pixels. Defaults to 1024x768.
+    /// 
+    /// **Note:** Only applied to Windows emulators run through Wine, using a virtual desktop.
+    /// Use [`Headless`](crate::headless::Headless) to pin the display size of native emulators.
+    pub display_size: Option<(u32, u32)>,
+}
+impl Default for Deterministic {
+    fn default() -> Self {
+        Self {
+            timezone: "UTC".into(),
+            locale: "C.UTF-8".into(),
+            audio: false,
+            display_size: Some((1024, 768)),
+        }
+    }
+}
+impl Deterministic {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn with_timezone<S: Into<String>>(self, timezone: S) -> Self {
+        Self {
+            timezone: timezone.into(),
+            ..self
+        }
+    }
+    
+    pub fn with_locale<S: Into<String>>(self, locale: S) -> Self {
+        Self {
+            locale: locale.into(),
+            ..self
+        }
+    }
+    
+    pub fn with_audio(self, audio: bool) -> Self {
+        Self {
+            audio,
+            ..self
+        }
+    }
+    
+    pub fn with_display_size(self, display_size: Option<(u32, u32)>) -> Self {
+        Self {
+            display_size,
+            ..self
+        }
+    }
+    
+    /// Returns the environment variables set by this preset.
+    pub fn vars(&self) -> Vec<(String, String)> {
+        let mut vars = vec![
+            ("TZ".to_string(), self.timezone.clone()),
+            ("LANG".into(), self.locale.clone()),
+            ("LC_ALL".into(), self.locale.clone()),
+        ];
+        
+        if !self.audio {
+            // SDL (FCEUX) and OpenAL Soft (BizHawk)
+            vars.push(("SDL_AUDIODRIVER".into(), "dummy".into()));
+            vars.push(("AUDIODEV".into(), "null".into()));
+            vars.push(("ALSOFT_DRIVERS".into(), "null".into()));
+        }
+        
+        vars
+    }
+    
+    /// Returns the Wine DLL overrides set by this preset.
+    pub fn wine_dll_overrides(&self) -> Vec<(String, String)> {
+        if self.audio {
+            return vec![];
+        }
+        
+        vec![(WINE_AUDIO_DRIVERS.into(), "d".into())]
+    }
+    
+    /// Returns the arguments which run a Windows executable in a fixed size Wine virtual desktop.
+    /// 
+    /// These go between the Wine loader and the executable.
+    pub fn wine_desktop_args(&self) -> Vec<String> {
+        match self.display_size {
+            Some((width, height)) => vec!["explorer".into(), format!("/desktop=emu-runner,{width}x{height}")],
+            None => vec![],
+        }
+    }
+}
\ No newline at end of file
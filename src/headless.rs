@@ -0,0 +1,213 @@
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use camino::Utf8PathBuf;
+use crate::{EmulatorContext, Error};
+use crate::doctor::{find_program, Requirement};
+use crate::env::EnvInheritance;
+use crate::limits::ResourceLimits;
+use crate::lock::LockMode;
+
+/// How long to wait for Xvfb to report its display before giving up.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs a context on a private virtual X server, so emulators can be run on machines without a display.
+/// 
+/// During `prepare`, `Xvfb` is started on the first free display number (starting from [`first_display`](Headless::first_display)),
+/// and `DISPLAY` is set in the emulator's environment. The server is shut down during `cleanup`.
+#[derive(Debug)]
+pub struct Headless<C: EmulatorContext> {
+    pub inner: C,
+    
+    /// Screen size and depth, as passed to Xvfb's `-screen 0` option. Defaults to `1024x768x24`.
+    pub screen: String,
+    
+    /// First display number tried when looking for a free display. Defaults to 99.
+    pub first_display: u32,
+    
+    display: Option<u32>,
+    server: Option<Child>,
+}
+impl<C: EmulatorContext> EmulatorContext for Headless<C> {
+    fn cmd_name(&self) -> String {
+        self.inner.cmd_name()
+    }
+    
+    fn args(&self) -> Vec<String> {
+        self.inner.args()
+    }
+    
+    fn env(&self) -> Vec<(String, String)> {
+        let mut vars = self.inner.env();
+        if let Some(display) = self.display {
+            vars.retain(|(key, _)| key != "DISPLAY");
+            vars.push(("DISPLAY".into(), format!(":{display}")));
+        }
+        
+        vars
+    }
+    
+    fn working_dir(&self) -> Utf8PathBuf {
+        self.inner.working_dir()
+    }
+    
+    fn prepare(&mut self) -> Result<(), Error> {
+        if self.server.is_none() {
+            self.start_server()?;
+        }
+        
+        self.inner.prepare()
+    }
+    
+    fn cleanup(&mut self) -> Result<(), Error> {
+        let result = self.inner.cleanup();
+        self.stop_server();
+        
+        result
+    }
+    
+    fn set_working_dir(&mut self, working_dir: Utf8PathBuf) -> Result<(), Error> {
+        self.inner.set_working_dir(working_dir)
+    }
+    
+    fn supports_concurrent_instances(&self) -> bool {
+        self.inner.supports_concurrent_instances()
+    }
+    
+    fn lock_mode(&self) -> LockMode {
+        self.inner.lock_mode()
+    }
+    
+    fn env_inheritance(&self) -> EnvInheritance {
+        self.inner.env_inheritance()
+    }
+    
+    fn resource_limits(&self) -> ResourceLimits {
+        self.inner.resource_limits()
+    }
+    
+    fn requirements(&self) -> Vec<Requirement> {
+        let mut requirements = self.inner.requirements();
+        requirements.push(Requirement::Xvfb);
+        
+        requirements
+    }
+}
+impl<C: EmulatorContext> Headless<C> {
+    /// Wraps a context so it's run on a private virtual X server.
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            screen: "1024x768x24".into(),
+            first_display: 99,
+            display: None,
+            server: None,
+        }
+    }
+    
+    pub fn with_screen<S: Into<String>>(mut self, screen: S) -> Self {
+        self.screen = screen.into();
+        self
+    }
+    
+    pub fn with_first_display(mut self, first_display: u32) -> Self {
+        self.first_display = first_display;
+        self
+    }
+    
+    /// Returns the display number of the running server, if the context has been prepared.
+    pub fn display(&self) -> Option<u32> {
+        self.display
+    }
+    
+    /// Starts Xvfb on the first free display.
+    /// 
+    /// Xvfb writes the display number to its stdout (`-displayfd`) once it accepts connections, which confirms that
+    /// this process's server started, rather than another one using the same display. If another server claims
+    /// a display between checking and starting, Xvfb exits without writing it, and the next display is tried.
+    fn start_server(&mut self) -> Result<(), Error> {
+        let xvfb = find_program("Xvfb").ok_or_else(|| Error::MissingXvfb("Xvfb".into()))?;
+        
+        for display in self.first_display..self.first_display.saturating_add(100) {
+            if display_in_use(display) {
+                continue;
+            }
+            
+            let (reader, writer) = std::io::pipe()?;
+            let mut child = Command::new(&xvfb)
+                .arg(format!(":{display}"))
+                .args(["-screen", "0", &self.screen])
+                .args(["-nolisten", "tcp"])
+                .args(["-displayfd", "1"])
+                .stdin(Stdio::null())
+                .stdout(writer)
+                .stderr(Stdio::null())
+                .spawn()?;
+            
+            // the write end is closed once the server exits, so the reader never outlives it
+            let (sender, receiver) = mpsc::channel();
+            std::thread::spawn(move || {
+                let mut line = String::new();
+                let reported = BufReader::new(reader).read_line(&mut line).ok().and_then(|_| line.trim().parse::<u32>().ok());
+                let _ = sender.send(reported);
+            });
+            
+            let started = Instant::now();
+            loop {
+                match receiver.recv_timeout(Duration::from_millis(25)) {
+                    Ok(Some(reported)) if reported == display => {
+                        self.display = Some(display);
+                        self.server = Some(child);
+                        return Ok(());
+                    },
+                    Ok(_) | Err(RecvTimeoutError::Disconnected) => {
+                        terminate(&mut child);
+                        break;
+                    },
+                    Err(RecvTimeoutError::Timeout) => (),
+                }
+                if started.elapsed() > STARTUP_TIMEOUT {
+                    terminate(&mut child);
+                    return Err(Error::XvfbFailed(format!(":{display}")));
+                }
+            }
+        }
+        
+        Err(Error::XvfbFailed(format!(":{}", self.first_display)))
+    }
+    
+    fn stop_server(&mut self) {
+        if let Some(mut child) = self.server.take() {
+            terminate(&mut child);
+        }
+        self.display = None;
+    }
+}
+impl<C: EmulatorContext> Drop for Headless<C> {
+    fn drop(&mut self) {
+        self.stop_server();
+    }
+}
+
+fn socket_path(display: u32) -> Utf8PathBuf {
+    format!("/tmp/.X11-unix/X{display}").into()
+}
+
+/// Returns true if a lock file or socket exists for the display.
+fn display_in_use(display: u32) -> bool {
+    Utf8PathBuf::from(format!("/tmp/.X{display}-lock")).exists() || socket_path(display).exists()
+}
+
+/// Asks the server to exit, so it removes its lock file and socket, then waits for it.
+fn terminate(child: &mut Child) {
+    #[cfg(target_family = "unix")]
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+    }
+    
+    #[cfg(target_family = "windows")]
+    let _ = child.kill();
+    
+    let _ = child.wait();
+}
\ No newline at end of file
@@ -1,24 +1,219 @@
-use camino::Utf8Path;
-use sha1_smol::Sha1;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use camino::{Utf8Path, Utf8PathBuf};
+use sha1_smol::{Digest, Sha1};
 
-pub const BIZHAWK_BASH_DEFAULT: &'static [u8] = include_bytes!("includes/start-bizhawk.sh");
-pub const BIZHAWK_BASH_PRE290: &'static [u8] = include_bytes!("includes/start-bizhawk-pre290.sh");
+pub const BIZHAWK_BASH_DEFAULT: &[u8] = include_bytes!("includes/start-bizhawk.sh");
+pub const BIZHAWK_BASH_PRE290: &[u8] = include_bytes!("includes/start-bizhawk-pre290.sh");
 
 /// Writes data to the destination path, replacing if destination file exists and SHA1 mismatches.
 /// 
+/// The file is replaced atomically (see [`write_atomic`]).
 /// Does _not_ create missing parent directories!
 pub fn copy_if_different<P: AsRef<Utf8Path>>(data: &[u8], dest: P) -> std::io::Result<()> {
     let dest = dest.as_ref();
     
-    if dest.is_file() {
-        let old = std::fs::read(&dest)?;
-        let hash_new = Sha1::from(&data).digest();
-        let hash_old = Sha1::from(&old).digest();
+    if !differs(data, dest)? {
+        return Ok(());
+    }
+    
+    write_atomic(data, dest)
+}
+
+/// Copies the source file to the destination path, replacing if destination file exists and SHA1 mismatches.
+/// 
+/// Neither file is loaded into memory. The file is replaced atomically (see [`copy_atomic`]).
+/// Does _not_ create missing parent directories!
+pub fn copy_file_if_different<P: AsRef<Utf8Path>, Q: AsRef<Utf8Path>>(src: P, dest: Q) -> std::io::Result<()> {
+    let (src, dest) = (src.as_ref(), dest.as_ref());
+    
+    if !files_differ(src, dest)? {
+        return Ok(());
+    }
+    
+    copy_atomic(src, dest)
+}
+
+/// Returns true if the destination file is missing, or its size or SHA1 doesn't match the data.
+pub fn differs<P: AsRef<Utf8Path>>(data: &[u8], dest: P) -> std::io::Result<bool> {
+    let dest = dest.as_ref();
+    
+    match dest.metadata() {
+        Ok(meta) if meta.is_file() && meta.len() == data.len() as u64 => {
+            Ok(hash_file(dest)? != Sha1::from(data).digest())
+        },
+        _ => Ok(true),
+    }
+}
+
+/// Returns true if the destination file is missing, or its size or SHA1 doesn't match the source file.
+pub fn files_differ<P: AsRef<Utf8Path>, Q: AsRef<Utf8Path>>(src: P, dest: Q) -> std::io::Result<bool> {
+    let (src, dest) = (src.as_ref(), dest.as_ref());
+    let src_len = src.metadata()?.len();
+    
+    match dest.metadata() {
+        Ok(meta) if meta.is_file() && meta.len() == src_len => {
+            Ok(hash_file(src)? != hash_file(dest)?)
+        },
+        _ => Ok(true),
+    }
+}
+
+/// Calculates the SHA1 of a file, reading it in small chunks.
+pub fn hash_file<P: AsRef<Utf8Path>>(path: P) -> std::io::Result<Digest> {
+    let mut file = File::open(path.as_ref())?;
+    let mut hasher = Sha1::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            len => hasher.update(&buf[..len]),
+        }
+    }
+    
+    Ok(hasher.digest())
+}
+
+/// Writes data to a temporary file next to the destination, then renames it over the destination.
+/// 
+/// The destination is never left partially written; it either has its old contents or the new ones.
+/// If the destination already exists, its permissions are kept.
+pub fn write_atomic<P: AsRef<Utf8Path>>(data: &[u8], dest: P) -> std::io::Result<()> {
+    let dest = dest.as_ref();
+    let temp = temp_path_for(dest);
+    
+    let result = File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            if let Ok(meta) = dest.metadata() {
+                file.set_permissions(meta.permissions())?;
+            }
+            file.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&temp, dest));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    
+    result
+}
+
+/// Copies a file to a temporary file next to the destination, then renames it over the destination.
+/// 
+/// The destination is never left partially written; it either has its old contents or the new ones.
+pub fn copy_atomic<P: AsRef<Utf8Path>, Q: AsRef<Utf8Path>>(src: P, dest: Q) -> std::io::Result<()> {
+    let (src, dest) = (src.as_ref(), dest.as_ref());
+    let temp = temp_path_for(dest);
+    
+    let result = std::fs::copy(src, &temp)
+        .and_then(|_| File::options().write(true).open(&temp))
+        .and_then(|file| file.sync_all())
+        .and_then(|_| std::fs::rename(&temp, dest));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    
+    result
+}
+
+/// Returns a unique, hidden path in the same directory as `dest`, so it can be renamed over it.
+fn temp_path_for(dest: &Utf8Path) -> Utf8PathBuf {
+    unique_path_for(dest, "tmp")
+}
+
+/// Returns a hidden path in the same directory as `path`, unique to this process and call, with the given extension.
+pub(crate) fn unique_path_for(path: &Utf8Path, extension: &str) -> Utf8PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    
+    let name = path.file_name().unwrap_or("file");
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    
+    path.with_file_name(format!(".{name}.emu-runner-{}-{count}.{extension}", std::process::id()))
+}
+
+/// Recursively copies a directory, recreating symlinks rather than following them.
+/// 
+/// Existing files in the destination are overwritten.
+pub fn copy_dir_all<P: AsRef<Utf8Path>, Q: AsRef<Utf8Path>>(src: P, dest: Q) -> std::io::Result<()> {
+    let (src, dest) = (src.as_ref(), dest.as_ref());
+    std::fs::create_dir_all(dest)?;
+    
+    for entry in src.read_dir_utf8()? {
+        let entry = entry?;
+        let target = dest.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        
+        if file_type.is_dir() {
+            copy_dir_all(entry.path(), target)?;
+        } else if file_type.is_symlink() {
+            if target.symlink_metadata().is_ok() {
+                std::fs::remove_file(&target)?;
+            }
+            
+            #[cfg(target_family = "unix")]
+            std::os::unix::fs::symlink(entry.path().read_link_utf8()?, target)?;
+            
+            #[cfg(target_family = "windows")]
+            std::fs::copy(entry.path(), target).map(|_| ())?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    
+    Ok(())
+}
+
+/// Creates a new, uniquely named, directory inside the system's temporary directory.
+/// 
+/// The caller is responsible for removing it.
+pub fn create_temp_dir(prefix: &str) -> std::io::Result<Utf8PathBuf> {
+    let base = Utf8PathBuf::try_from(std::env::temp_dir())
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+    
+    create_temp_dir_in(base, prefix)
+}
+
+/// Creates a new, uniquely named, directory inside the given directory.
+/// 
+/// The caller is responsible for removing it.
+pub fn create_temp_dir_in<P: AsRef<Utf8Path>>(base: P, prefix: &str) -> std::io::Result<Utf8PathBuf> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    
+    let base = base.as_ref();
+    loop {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.subsec_nanos()).unwrap_or(0);
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let dir = base.join(format!("{prefix}-{}-{count}-{nanos:08x}", std::process::id()));
         
-        if hash_new == hash_old {
-            return Ok(());
+        match std::fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
     
-    std::fs::write(dest, data)
-}
\ No newline at end of file
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn write_atomic_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let dir = create_temp_dir("emu-runner-test").unwrap();
+        let path = dir.join("start.sh");
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o751)).unwrap();
+        
+        write_atomic(b"new", &path).unwrap();
+        
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(path.metadata().unwrap().permissions().mode() & 0o777, 0o751);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,26 +1,66 @@
+use std::ops::Deref;
 use std::process::{Command, Output};
 use camino::Utf8PathBuf;
+use lock::{LockMode, WorkingDirLock};
 
 pub mod contexts;
+pub mod definition;
+pub mod doctor;
+pub mod env;
+pub mod headless;
 pub mod includes;
+pub mod limits;
+pub mod lock;
+pub mod manifest;
+pub mod namespace;
+pub mod pe;
+pub mod placement;
+pub mod sandbox;
+pub mod wine;
+pub mod wrapper;
 
 #[derive(Debug)]
 pub enum Error {
     StdIo(std::io::Error),
+    Json(serde_json::Error),
+    /// An [emulator definition](definition::EmulatorDefinition) couldn't be parsed.
+    Toml(toml::de::Error),
     MissingExecutable(Utf8PathBuf),
     MissingBash(Utf8PathBuf),
+    MissingWine(Utf8PathBuf),
+    MissingMono(Utf8PathBuf),
+    MissingXvfb(Utf8PathBuf),
+    NotExecutable(Utf8PathBuf),
     MissingConfig(Utf8PathBuf),
     MissingRom(Utf8PathBuf),
     MissingMovie(Utf8PathBuf),
     MissingLua(Utf8PathBuf),
+    /// A file required by a [`CustomContext`](contexts::CustomContext) doesn't exist.
+    MissingFile(Utf8PathBuf),
     IncompatibleOSVersion,
     AbsolutePathFailed,
+    /// An emulator's config format can't store the given setting.
+    UnsupportedSetting(String),
+    /// A Wine command (e.g. `wineboot` or `wineserver`) failed for the given prefix.
+    WinePrefixFailed(Utf8PathBuf),
+    /// The virtual X server failed to start on the given display (e.g. `:99`).
+    XvfbFailed(String),
 }
 impl From<std::io::Error> for Error {
     fn from(value: std::io::Error) -> Self {
         Self::StdIo(value)
     }
 }
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+impl From<toml::de::Error> for Error {
+    fn from(value: toml::de::Error) -> Self {
+        Self::Toml(value)
+    }
+}
 
 /// Behavior used to run an emulator.
 pub trait EmulatorContext: Sized {
@@ -47,29 +87,361 @@ pub trait EmulatorContext: Sized {
     /// Returns an error if preparation failed.
     fn prepare(&mut self) -> Result<(), Error>;
     
+    /// Undo any temporary changes made by [`prepare`](EmulatorContext::prepare), once the emulator has exited.
+    /// 
+    /// Also called if preparation fails part way through. Default trait implementation does nothing.
+    fn cleanup(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+    
+    /// Points the context at a different copy of the emulator's directory.
+    /// 
+    /// Used by [`Sandboxed`](sandbox::Sandboxed). Default trait implementation returns [`Error::UnsupportedSetting`].
+    fn set_working_dir(&mut self, working_dir: Utf8PathBuf) -> Result<(), Error> {
+        Err(Error::UnsupportedSetting(format!("working_dir = {working_dir}")))
+    }
+    
+    /// Returns true if multiple instances of the emulator can safely run from the same working directory at once.
+    /// 
+    /// Default trait implementation returns false.
+    fn supports_concurrent_instances(&self) -> bool {
+        false
+    }
+    
+    /// Returns how the working directory is locked by [`run`].
+    /// 
+    /// Default trait implementation locks [`Shared`](lock::LockMode::Shared) if the context
+    /// [supports concurrent instances](EmulatorContext::supports_concurrent_instances), otherwise [`Exclusive`](lock::LockMode::Exclusive).
+    fn lock_mode(&self) -> lock::LockMode {
+        if self.supports_concurrent_instances() {
+            lock::LockMode::Shared
+        } else {
+            lock::LockMode::Exclusive
+        }
+    }
+    
+    /// Returns which variables the emulator inherits from the parent process's environment, before [`env`](EmulatorContext::env) is applied.
+    /// 
+    /// Default trait implementation inherits [everything](env::EnvInheritance::All).
+    fn env_inheritance(&self) -> env::EnvInheritance {
+        env::EnvInheritance::All
+    }
+    
+    /// Returns the resource limits applied to the emulator's process by [`command`].
+    /// 
+    /// Default trait implementation has no limits. See [`Limited`](limits::Limited) to add limits to any context.
+    fn resource_limits(&self) -> limits::ResourceLimits {
+        limits::ResourceLimits::default()
+    }
+    
+    /// Returns the host programs which must be available to run this context.
+    /// 
+    /// Default trait implementation has no requirements.
+    fn requirements(&self) -> Vec<doctor::Requirement> {
+        vec![]
+    }
+    
+    /// Checks the host system for this context's [requirements](EmulatorContext::requirements).
+    /// 
+    /// Default trait implementation simply calls [`doctor()`].
+    fn doctor(&self) -> doctor::Report {
+        doctor(self)
+    }
+    
     /// Creates and executes a [`Command`] and returns the output result.
     /// 
     /// Default trait implementation simply calls [`run`].
-    fn run(self) -> Result<Output, Error> {
+    fn run(self) -> Result<RunOutput, Error> {
         run(self)
     }
 }
 
+/// Options shared by emulator contexts, so generic code can configure any emulator without knowing its concrete type.
+/// 
+/// Paths given to the `with_*` builders are [normalized](placement::normalize_path), so relative paths keep
+/// pointing at the same file once the emulator is run from its own working directory.
+pub trait CommonOptions: EmulatorContext {
+    /// Sets the ROM loaded by the emulator. The path is used as-is.
+    fn set_rom(&mut self, rom: Option<Utf8PathBuf>);
+    
+    /// Sets the movie played back by the emulator. The path is used as-is.
+    fn set_movie(&mut self, movie: Option<Utf8PathBuf>);
+    
+    /// Sets the Lua script run by the emulator. The path is used as-is.
+    fn set_lua(&mut self, lua: Option<Utf8PathBuf>);
+    
+    /// Sets the config used by the emulator. The path is used as-is.
+    fn set_config(&mut self, config: Option<Utf8PathBuf>);
+    
+    /// Sets whether the emulator starts paused.
+    /// 
+    /// Default trait implementation returns [`Error::UnsupportedSetting`] when pausing is requested.
+    fn set_pause(&mut self, start_paused: bool) -> Result<(), Error> {
+        if start_paused {
+            return Err(Error::UnsupportedSetting(format!("start_paused = {start_paused}")));
+        }
+        
+        Ok(())
+    }
+    
+    fn with_rom<P: Into<Utf8PathBuf>>(mut self, rom: P) -> Self {
+        self.set_rom(Some(placement::normalize_path(rom)));
+        self
+    }
+    
+    fn with_movie<P: Into<Utf8PathBuf>>(mut self, movie: P) -> Self {
+        self.set_movie(Some(placement::normalize_path(movie)));
+        self
+    }
+    
+    fn with_lua<P: Into<Utf8PathBuf>>(mut self, lua: P) -> Self {
+        self.set_lua(Some(placement::normalize_path(lua)));
+        self
+    }
+    
+    fn with_config<P: Into<Utf8PathBuf>>(mut self, config: P) -> Self {
+        self.set_config(Some(placement::normalize_path(config)));
+        self
+    }
+    
+    /// Sets whether the emulator starts paused, returning an error if the emulator doesn't support it (see [`set_pause`](CommonOptions::set_pause)).
+    fn with_pause(mut self, start_paused: bool) -> Result<Self, Error> {
+        self.set_pause(start_paused)?;
+        
+        Ok(self)
+    }
+}
+
 /// Prepares and executes an emulator based on the provided context.
 /// 
 /// Returns any errors encountered while preparing (context-dependent) and any IO errors caused by running the command.
-pub fn run<C: EmulatorContext>(mut ctx: C) -> Result<Output, Error> {
-    ctx.prepare()?;
+/// The context is always [cleaned up](EmulatorContext::cleanup) afterwards.
+/// 
+/// The working directory is locked according to [`EmulatorContext::lock_mode`] for the whole cycle.
+/// Preparation and cleanup additionally hold the directory's [prepare lock](WorkingDirLock::acquire_prepare),
+/// so shared runs never prepare or clean up at the same time.
+pub fn run<C: EmulatorContext>(mut ctx: C) -> Result<RunOutput, Error> {
+    let mode = ctx.lock_mode();
+    let working_dir = ctx.working_dir();
+    let _lock = match mode {
+        LockMode::Unlocked => None,
+        _ => Some(WorkingDirLock::acquire(&working_dir, mode)?),
+    };
+    let prepare_lock = || match mode {
+        LockMode::Unlocked => Ok(None),
+        _ => WorkingDirLock::acquire_prepare(&working_dir).map(Some),
+    };
     
-    command(ctx).output().map_err(|err| err.into())
+    {
+        let _prepare_lock = prepare_lock()?;
+        if let Err(err) = ctx.prepare() {
+            let _ = ctx.cleanup();
+            return Err(err);
+        }
+    }
+    
+    let output = execute(&ctx);
+    
+    {
+        let _prepare_lock = prepare_lock()?;
+        ctx.cleanup()?;
+    }
+    
+    output.map_err(|err| err.into())
+}
+
+/// Output of an emulator run by [`run`], along with the resources its process used.
+/// 
+/// Dereferences to the [`Output`], so the exit status and captured stdout/stderr can be used directly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunOutput {
+    pub output: Output,
+    
+    /// Resource usage of the emulator's process. Only available on unix.
+    pub usage: Option<limits::ResourceUsage>,
+}
+impl Deref for RunOutput {
+    type Target = Output;
+    
+    fn deref(&self) -> &Self::Target {
+        &self.output
+    }
+}
+
+/// Executes the context's command and collects its output, like [`Command::output`], along with its resource usage.
+fn execute<C: EmulatorContext>(ctx: &C) -> std::io::Result<RunOutput> {
+    #[cfg(target_family = "unix")]
+    {
+        use std::io::Read;
+        use std::process::Stdio;
+        
+        let mut child = command(ctx)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        
+        let mut stdout_pipe = child.stdout.take().unwrap();
+        let mut stderr_pipe = child.stderr.take().unwrap();
+        let stderr_reader = std::thread::spawn(move || {
+            let mut stderr = vec![];
+            stderr_pipe.read_to_end(&mut stderr).map(|_| stderr)
+        });
+        
+        let mut stdout = vec![];
+        let stdout_result = stdout_pipe.read_to_end(&mut stdout);
+        let stderr_result = stderr_reader.join().unwrap_or_else(|_| Err(std::io::Error::other("failed to read stderr")));
+        
+        // always reap the process, even if reading its output failed
+        let (status, usage) = limits::wait(&child)?;
+        stdout_result?;
+        
+        Ok(RunOutput {
+            output: Output { status, stdout, stderr: stderr_result? },
+            usage: Some(usage),
+        })
+    }
+    
+    #[cfg(target_family = "windows")]
+    {
+        Ok(RunOutput {
+            output: command(ctx).output()?,
+            usage: None,
+        })
+    }
+}
+
+/// Checks that every host program needed by the context exists, is executable, and reports its version.
+/// 
+/// Use [`doctor::Report::into_result`] to turn the first missing program into an [`Error`].
+pub fn doctor<C: EmulatorContext>(ctx: &C) -> doctor::Report {
+    doctor::check(&ctx.requirements())
 }
 
 /// Buildes a [`Command`] using data pulled from an [`EmulatorContext`].
-pub fn command<C: EmulatorContext>(ctx: C) -> Command {
+pub fn command<C: EmulatorContext>(ctx: &C) -> Command {
+    command_with_args(ctx, ctx.args())
+}
+
+/// Builds a [`Command`] like [`command`], passing `args` instead of the context's own arguments.
+pub(crate) fn command_with_args<C: EmulatorContext>(ctx: &C, args: Vec<String>) -> Command {
     let mut cmd = Command::new(ctx.cmd_name());
-    cmd.args(ctx.args())
+    if let Some(inherited) = ctx.env_inheritance().inherited_vars() {
+        cmd.env_clear().envs(inherited);
+    }
+    cmd.args(args)
         .envs(ctx.env())
         .current_dir(ctx.working_dir());
+    ctx.resource_limits().apply(&mut cmd);
     
     cmd
-}
\ No newline at end of file
+}
+
+/// Renders the command that [`run`] would execute, as a single shell-style line, without preparing or executing anything.
+/// 
+/// The line changes directory into the working directory, then sets each environment variable before the command.
+/// If the context doesn't [inherit the whole environment](EmulatorContext::env_inheritance), it's cleared with `env -i` first.
+/// Values containing whitespace or shell characters are single-quoted.
+pub fn dry_run<C: EmulatorContext>(ctx: &C) -> String {
+    let mut parts = vec!["cd".to_string(), quote(ctx.working_dir().as_str()), "&&".into()];
+    if let Some(inherited) = ctx.env_inheritance().inherited_vars() {
+        parts.extend(["env".into(), "-i".into()]);
+        parts.extend(inherited.iter().map(|(key, value)| format!("{key}={}", quote(value))));
+    }
+    parts.extend(ctx.env().iter().map(|(key, value)| format!("{key}={}", quote(value))));
+    parts.push(quote(&ctx.cmd_name()));
+    parts.extend(ctx.args().iter().map(|arg| quote(arg)));
+    
+    parts.join(" ")
+}
+
+fn quote(value: &str) -> String {
+    let safe = !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=,+@%".contains(c));
+    if safe {
+        return value.to_string();
+    }
+    
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env::EnvInheritance;
+    
+    struct TestContext {
+        env_inheritance: EnvInheritance,
+    }
+    impl EmulatorContext for TestContext {
+        fn cmd_name(&self) -> String {
+            "/opt/my emulator/run.sh".into()
+        }
+        
+        fn args(&self) -> Vec<String> {
+            vec!["--rom".into(), "Tom's Game (USA).nes".into(), "--speed=100%".into(), "".into()]
+        }
+        
+        fn env(&self) -> Vec<(String, String)> {
+            vec![("LANG".into(), "C.UTF-8".into()), ("TITLE".into(), "$HOME `id`".into())]
+        }
+        
+        fn working_dir(&self) -> Utf8PathBuf {
+            "/tmp/emu".into()
+        }
+        
+        fn prepare(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        fn env_inheritance(&self) -> EnvInheritance {
+            self.env_inheritance.clone()
+        }
+    }
+    
+    #[test]
+    fn quote_leaves_safe_values() {
+        for value in ["game.nes", "/tmp/roms/game.nes", "C:game", "--speed=100%", "user@host", "a,b+c"] {
+            assert_eq!(quote(value), value);
+        }
+    }
+    
+    #[test]
+    fn quote_wraps_unsafe_values() {
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("Super Mario Bros.nes"), "'Super Mario Bros.nes'");
+        assert_eq!(quote("$HOME"), "'$HOME'");
+        assert_eq!(quote("a;rm -rf b"), "'a;rm -rf b'");
+        assert_eq!(quote("C:\\roms"), "'C:\\roms'");
+        assert_eq!(quote("ポケモン"), "'ポケモン'");
+        assert_eq!(quote("Tom's"), "'Tom'\\''s'");
+    }
+    
+    #[test]
+    fn dry_run_renders_command() {
+        let ctx = TestContext { env_inheritance: EnvInheritance::All };
+        
+        assert_eq!(
+            dry_run(&ctx),
+            "cd /tmp/emu && LANG=C.UTF-8 TITLE='$HOME `id`' '/opt/my emulator/run.sh' --rom 'Tom'\\''s Game (USA).nes' --speed=100% ''",
+        );
+    }
+    
+    #[test]
+    fn dry_run_clears_environment() {
+        let ctx = TestContext { env_inheritance: EnvInheritance::Clean };
+        
+        assert!(dry_run(&ctx).starts_with("cd /tmp/emu && env -i LANG=C.UTF-8 TITLE="));
+    }
+    
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn quoted_values_survive_the_shell() {
+        let values = ["Tom's Game (USA).nes", "$HOME `id`", "a\"b\\c", "", "line\nbreak", "'''"];
+        let script = format!("printf '%s\\0' {}", values.iter().map(|value| quote(value)).collect::<Vec<_>>().join(" "));
+        
+        let output = Command::new("sh").args(["-c", &script]).output().unwrap();
+        let printed: Vec<_> = output.stdout.split(|byte| *byte == 0).map(|value| String::from_utf8_lossy(value).into_owned()).collect();
+        
+        assert_eq!(printed[..values.len()], values);
+    }
+}
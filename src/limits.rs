@@ -0,0 +1,213 @@
+use std::process::Command;
+use std::time::Duration;
+use camino::Utf8PathBuf;
+use crate::{EmulatorContext, Error};
+use crate::doctor::Requirement;
+use crate::env::EnvInheritance;
+use crate::lock::LockMode;
+
+/// Limits applied to the emulator's process (and any processes it starts) before it's executed.
+/// 
+/// **Note:** Only applied on unix, using `setrlimit`. Limits which are `None` are inherited from emu-runner.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ResourceLimits {
+    /// Maximum CPU time. The process is killed once it's used this much (`RLIMIT_CPU`).
+    pub cpu_time: Option<Duration>,
+    /// Maximum size of the process's virtual memory, in bytes (`RLIMIT_AS`).
+    /// 
+    /// Wine and mono reserve large amounts of address space up front, so this should be set generously for them.
+    pub address_space: Option<u64>,
+    /// Maximum number of open file descriptors (`RLIMIT_NOFILE`).
+    pub open_files: Option<u64>,
+}
+impl ResourceLimits {
+    pub fn with_cpu_time(self, cpu_time: Duration) -> Self {
+        Self {
+            cpu_time: Some(cpu_time),
+            ..self
+        }
+    }
+    
+    pub fn with_address_space(self, address_space: u64) -> Self {
+        Self {
+            address_space: Some(address_space),
+            ..self
+        }
+    }
+    
+    pub fn with_open_files(self, open_files: u64) -> Self {
+        Self {
+            open_files: Some(open_files),
+            ..self
+        }
+    }
+    
+    /// Returns these limits, using the other limits for any which aren't set.
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            cpu_time: self.cpu_time.or(other.cpu_time),
+            address_space: self.address_space.or(other.address_space),
+            open_files: self.open_files.or(other.open_files),
+        }
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+    
+    /// Sets up the command so the limits are applied in the child process, just before the emulator is executed.
+    #[cfg_attr(target_family = "windows", allow(unused_variables))]
+    pub(crate) fn apply(&self, cmd: &mut Command) {
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::process::CommandExt;
+            
+            if self.is_empty() {
+                return;
+            }
+            
+            // round CPU time up, so a limit of less than a second isn't treated as no limit
+            let cpu_time = self.cpu_time.map(|time| time.as_secs() + (time.subsec_nanos() > 0) as u64);
+            let limits = [
+                (libc::RLIMIT_CPU, cpu_time),
+                (libc::RLIMIT_AS, self.address_space),
+                (libc::RLIMIT_NOFILE, self.open_files),
+            ];
+            
+            // Safety: only calls setrlimit, which is async-signal-safe, and doesn't allocate.
+            unsafe {
+                cmd.pre_exec(move || {
+                    for (resource, limit) in limits {
+                        if let Some(limit) = limit {
+                            let limit = libc::rlimit {
+                                rlim_cur: limit as libc::rlim_t,
+                                rlim_max: limit as libc::rlim_t,
+                            };
+                            if libc::setrlimit(resource, &limit) != 0 {
+                                return Err(std::io::Error::last_os_error());
+                            }
+                        }
+                    }
+                    
+                    Ok(())
+                });
+            }
+        }
+    }
+}
+
+/// Resources used by the emulator's process, as reported by `wait4`.
+/// 
+/// Includes any processes it started and waited for (e.g. mono, when BizHawk is launched by its bash script).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ResourceUsage {
+    /// CPU time spent in user mode.
+    pub user_time: Duration,
+    /// CPU time spent in the kernel.
+    pub system_time: Duration,
+    /// Peak resident set size, in bytes.
+    pub max_rss: u64,
+}
+
+/// Runs a context with [resource limits](ResourceLimits) applied to the emulator's process.
+/// 
+/// Limits set on this wrapper take priority over any set by the inner context.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limited<C: EmulatorContext> {
+    pub inner: C,
+    pub limits: ResourceLimits,
+}
+impl<C: EmulatorContext> EmulatorContext for Limited<C> {
+    fn cmd_name(&self) -> String {
+        self.inner.cmd_name()
+    }
+    
+    fn args(&self) -> Vec<String> {
+        self.inner.args()
+    }
+    
+    fn env(&self) -> Vec<(String, String)> {
+        self.inner.env()
+    }
+    
+    fn working_dir(&self) -> Utf8PathBuf {
+        self.inner.working_dir()
+    }
+    
+    fn prepare(&mut self) -> Result<(), Error> {
+        self.inner.prepare()
+    }
+    
+    fn cleanup(&mut self) -> Result<(), Error> {
+        self.inner.cleanup()
+    }
+    
+    fn set_working_dir(&mut self, working_dir: Utf8PathBuf) -> Result<(), Error> {
+        self.inner.set_working_dir(working_dir)
+    }
+    
+    fn supports_concurrent_instances(&self) -> bool {
+        self.inner.supports_concurrent_instances()
+    }
+    
+    fn lock_mode(&self) -> LockMode {
+        self.inner.lock_mode()
+    }
+    
+    fn env_inheritance(&self) -> EnvInheritance {
+        self.inner.env_inheritance()
+    }
+    
+    fn resource_limits(&self) -> ResourceLimits {
+        self.limits.or(self.inner.resource_limits())
+    }
+    
+    fn requirements(&self) -> Vec<Requirement> {
+        self.inner.requirements()
+    }
+}
+impl<C: EmulatorContext> Limited<C> {
+    /// Wraps a context so its emulator process is run with the given limits.
+    pub fn new(inner: C, limits: ResourceLimits) -> Self {
+        Self {
+            inner,
+            limits,
+        }
+    }
+}
+
+/// Waits for the process to exit, returning its exit status and resource usage.
+#[cfg(target_family = "unix")]
+pub(crate) fn wait(child: &std::process::Child) -> std::io::Result<(std::process::ExitStatus, ResourceUsage)> {
+    use std::os::unix::process::ExitStatusExt;
+    
+    let mut status = 0;
+    // Safety: rusage is plain data, for which all zeroes is valid.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // Safety: status and usage are valid for writes, and the child hasn't been waited on yet.
+        let pid = unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut usage) };
+        if pid >= 0 {
+            break;
+        }
+        
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    
+    let duration = |time: libc::timeval| Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64);
+    
+    // linux reports kilobytes, macOS reports bytes
+    #[cfg(target_os = "macos")]
+    let max_rss = usage.ru_maxrss as u64;
+    #[cfg(not(target_os = "macos"))]
+    let max_rss = usage.ru_maxrss as u64 * 1024;
+    
+    Ok((std::process::ExitStatus::from_raw(status), ResourceUsage {
+        user_time: duration(usage.ru_utime),
+        system_time: duration(usage.ru_stime),
+        max_rss,
+    }))
+}
\ No newline at end of file
@@ -0,0 +1,78 @@
+use std::fs::File;
+use camino::{Utf8Path, Utf8PathBuf};
+use crate::Error;
+
+/// Name of the lock file created inside an emulator's working directory.
+pub const LOCK_FILE_NAME: &str = ".emu-runner.lock";
+
+/// Name of the lock file which serializes `prepare` and `cleanup` between runs sharing a working directory.
+pub const PREPARE_LOCK_FILE_NAME: &str = ".emu-runner.prepare.lock";
+
+/// How a working directory is locked while an emulator runs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LockMode {
+    /// Only one run may use the directory at a time.
+    Exclusive,
+    /// Runs may share the directory, but `prepare` and `cleanup` still happen one at a time.
+    Shared,
+    /// No lock is taken.
+    Unlocked,
+}
+
+/// An advisory lock on an emulator's working directory, released when dropped.
+/// 
+/// The lock is held on a `.emu-runner.lock` file inside the directory, so it only guards against other
+/// processes which use the same lock (e.g. other programs using emu-runner).
+/// 
+/// Shared runs additionally take the [prepare lock](Self::acquire_prepare) around `prepare` and `cleanup`,
+/// so only one of them modifies the directory at a time, without ever releasing their shared lock.
+#[derive(Debug)]
+pub struct WorkingDirLock {
+    file: File,
+    path: Utf8PathBuf,
+    mode: LockMode,
+}
+impl WorkingDirLock {
+    /// Blocks until the working directory can be locked with the given mode.
+    pub fn acquire<P: AsRef<Utf8Path>>(working_dir: P, mode: LockMode) -> Result<Self, Error> {
+        Self::acquire_file(working_dir.as_ref().join(LOCK_FILE_NAME), mode)
+    }
+    
+    /// Blocks until the working directory's prepare lock can be taken exclusively.
+    pub fn acquire_prepare<P: AsRef<Utf8Path>>(working_dir: P) -> Result<Self, Error> {
+        Self::acquire_file(working_dir.as_ref().join(PREPARE_LOCK_FILE_NAME), LockMode::Exclusive)
+    }
+    
+    fn acquire_file(path: Utf8PathBuf, mode: LockMode) -> Result<Self, Error> {
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+        
+        match mode {
+            LockMode::Exclusive => file.lock()?,
+            LockMode::Shared => file.lock_shared()?,
+            LockMode::Unlocked => (),
+        }
+        
+        Ok(Self {
+            file,
+            path,
+            mode,
+        })
+    }
+    
+    pub fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+    
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+}
+impl Drop for WorkingDirLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
\ No newline at end of file
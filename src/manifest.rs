@@ -0,0 +1,393 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use crate::includes::{copy_atomic, differs, files_differ, unique_path_for, write_atomic};
+
+/// A change made to the file system while preparing a context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestEntry {
+    /// A file that didn't exist before. Deleted when restored.
+    Created(Utf8PathBuf),
+    /// A file that was replaced. The original is kept at `backup` until restored.
+    Replaced { path: Utf8PathBuf, backup: Utf8PathBuf },
+    /// A file which another run had already written with the same contents, and is still using.
+    /// Whichever run restores it last puts back the original.
+    Shared(Utf8PathBuf),
+    /// A directory that didn't exist before. Deleted when restored, if empty.
+    CreatedDir(Utf8PathBuf),
+}
+
+/// Record of every file written or replaced by [`prepare`](crate::EmulatorContext::prepare),
+/// so that [`cleanup`](crate::EmulatorContext::cleanup) can put things back the way they were.
+/// 
+/// While a file is in use, the number of runs using it is kept in a hidden `.<name>.emu-runner-users` file next to it,
+/// so runs sharing a working directory (see [`LockMode::Shared`](crate::lock::LockMode::Shared)) never restore or
+/// delete a file another run still needs. A run which needs different contents in a file that's in use fails with
+/// [`ErrorKind::ResourceBusy`](std::io::ErrorKind::ResourceBusy). This relies on `prepare` and `cleanup` never
+/// running at the same time in one directory, which [`run`](crate::run) guarantees. If a run is killed before
+/// cleaning up, its users file is left behind along with its changes, and must be removed by hand.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Manifest {
+    entries: Vec<ManifestEntry>,
+}
+impl Manifest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    
+    /// Returns true if the path has already been recorded.
+    pub fn contains<P: AsRef<Utf8Path>>(&self, path: P) -> bool {
+        let path = path.as_ref();
+        
+        self.entries.iter().any(|entry| match entry {
+            ManifestEntry::Created(other) | ManifestEntry::Shared(other) | ManifestEntry::CreatedDir(other) => other == path,
+            ManifestEntry::Replaced { path: other, .. } => other == path,
+        })
+    }
+    
+    /// Returns where the original contents of a replaced file are kept, if it was replaced.
+    pub fn original<P: AsRef<Utf8Path>>(&self, path: P) -> Option<&Utf8Path> {
+        let path = path.as_ref();
+        
+        self.entries.iter().find_map(|entry| match entry {
+            ManifestEntry::Replaced { path: other, backup } if other == path => Some(backup.as_path()),
+            _ => None,
+        })
+    }
+    
+    /// Records the current state of a file which is about to be written.
+    /// 
+    /// An existing file is backed up under a unique name, by hard link if possible, otherwise by copy. The file
+    /// itself stays in place, so it can be replaced in a single rename. Paths that have already been recorded are
+    /// left alone, so the backup always holds the state from before the first change.
+    pub fn record<P: AsRef<Utf8Path>>(&mut self, path: P) -> std::io::Result<()> {
+        let path = path.as_ref();
+        if self.contains(path) {
+            return Ok(());
+        }
+        if Users::load(path)?.is_some() {
+            return Err(std::io::Error::new(std::io::ErrorKind::ResourceBusy, format!("{path} is in use by another run")));
+        }
+        
+        if path.is_file() {
+            let backup = unique_path_for(path, "backup");
+            if std::fs::hard_link(path, &backup).is_err() {
+                std::fs::copy(path, &backup)?;
+            }
+            
+            Users { original: Some(backup.clone()), count: 1 }.save(path)?;
+            self.entries.push(ManifestEntry::Replaced { path: path.to_path_buf(), backup });
+        } else {
+            Users { original: None, count: 1 }.save(path)?;
+            self.entries.push(ManifestEntry::Created(path.to_path_buf()));
+        }
+        
+        Ok(())
+    }
+    
+    /// Records that a file already has the wanted contents. If another run wrote it, this run is counted
+    /// as one of its users, so it isn't restored until this manifest is too.
+    fn join(&mut self, path: &Utf8Path) -> std::io::Result<()> {
+        if self.contains(path) {
+            return Ok(());
+        }
+        
+        if let Some(mut users) = Users::load(path)? {
+            users.count += 1;
+            users.save(path)?;
+            self.entries.push(ManifestEntry::Shared(path.to_path_buf()));
+        }
+        
+        Ok(())
+    }
+    
+    /// Writes data to the destination path if it differs, recording the change.
+    /// 
+    /// Does _not_ create missing parent directories! Use [`Manifest::create_dir_all`] for that.
+    pub fn write<P: AsRef<Utf8Path>>(&mut self, data: &[u8], dest: P) -> std::io::Result<()> {
+        let dest = dest.as_ref();
+        if !differs(data, dest)? {
+            return self.join(dest);
+        }
+        
+        self.record(dest)?;
+        write_atomic(data, dest)
+    }
+    
+    /// Copies the source file to the destination path if it differs, recording the change.
+    /// 
+    /// The source is never loaded into memory, so this is preferred for large files (e.g. disc images).
+    /// Does _not_ create missing parent directories! Use [`Manifest::create_dir_all`] for that.
+    pub fn copy<P: AsRef<Utf8Path>, Q: AsRef<Utf8Path>>(&mut self, src: P, dest: Q) -> std::io::Result<()> {
+        let (src, dest) = (src.as_ref(), dest.as_ref());
+        if !files_differ(src, dest)? {
+            return self.join(dest);
+        }
+        
+        self.record(dest)?;
+        copy_atomic(src, dest)
+    }
+    
+    /// Creates a symbolic link at the destination path pointing to the source, recording the change.
+    /// 
+    /// Does _not_ create missing parent directories! Use [`Manifest::create_dir_all`] for that.
+    pub fn symlink<P: AsRef<Utf8Path>, Q: AsRef<Utf8Path>>(&mut self, src: P, dest: Q) -> std::io::Result<()> {
+        let (src, dest) = (src.as_ref(), dest.as_ref());
+        if dest.read_link_utf8().ok().as_deref() == Some(src) {
+            return self.join(dest);
+        }
+        self.replace(dest)?;
+        
+        #[cfg(target_family = "unix")]
+        { std::os::unix::fs::symlink(src, dest) }
+        
+        #[cfg(target_family = "windows")]
+        { std::os::windows::fs::symlink_file(src, dest) }
+    }
+    
+    /// Creates a hard link at the destination path to the source, recording the change.
+    /// 
+    /// Does _not_ create missing parent directories! Use [`Manifest::create_dir_all`] for that.
+    pub fn hard_link<P: AsRef<Utf8Path>, Q: AsRef<Utf8Path>>(&mut self, src: P, dest: Q) -> std::io::Result<()> {
+        let (src, dest) = (src.as_ref(), dest.as_ref());
+        if dest.symlink_metadata().is_ok() && !files_differ(src, dest)? {
+            return self.join(dest);
+        }
+        self.replace(dest)?;
+        
+        std::fs::hard_link(src, dest)
+    }
+    
+    /// Creates a directory and any missing parents, recording each one created.
+    pub fn create_dir_all<P: AsRef<Utf8Path>>(&mut self, dir: P) -> std::io::Result<()> {
+        let dir = dir.as_ref();
+        if dir.is_dir() {
+            return Ok(());
+        }
+        
+        if let Some(parent) = dir.parent() {
+            self.create_dir_all(parent)?;
+        }
+        
+        std::fs::create_dir(dir)?;
+        self.entries.push(ManifestEntry::CreatedDir(dir.to_path_buf()));
+        
+        Ok(())
+    }
+    
+    /// Restores replaced files and deletes created ones, in the reverse order they were recorded.
+    /// 
+    /// Files which other runs are still using are left as they are, for the last of them to restore.
+    /// The manifest is empty afterwards.
+    pub fn restore(&mut self) -> std::io::Result<()> {
+        while let Some(entry) = self.entries.pop() {
+            match entry {
+                ManifestEntry::Created(path) => release(&path, None)?,
+                ManifestEntry::Replaced { path, backup } => release(&path, Some(backup))?,
+                ManifestEntry::Shared(path) => if let Some(users) = Users::load(&path)? {
+                    release(&path, users.original)?;
+                },
+                ManifestEntry::CreatedDir(dir) => {
+                    // anything else left inside was written by the emulator, so keep it
+                    let _ = std::fs::remove_dir(dir);
+                },
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+impl Manifest {
+    /// Records the path, then removes anything left there by an earlier change, so a new file can be created.
+    fn replace(&mut self, path: &Utf8Path) -> std::io::Result<()> {
+        self.record(path)?;
+        if path.symlink_metadata().is_ok() {
+            std::fs::remove_file(path)?;
+        }
+        
+        Ok(())
+    }
+}
+
+/// Stops using a file, putting back the original (or deleting the file, if there was none) once no run uses it.
+/// 
+/// `original` is only used if the file's users weren't recorded.
+fn release(path: &Utf8Path, original: Option<Utf8PathBuf>) -> std::io::Result<()> {
+    let original = match Users::load(path)? {
+        Some(mut users) if users.count > 1 => {
+            users.count -= 1;
+            return users.save(path);
+        },
+        Some(users) => {
+            std::fs::remove_file(Users::path_for(path))?;
+            users.original
+        },
+        None => original,
+    };
+    
+    match original {
+        Some(backup) => {
+            std::fs::rename(&backup, path)?;
+            
+            // renaming does nothing if the file was never replaced, since the backup is a link to the same file
+            if backup.symlink_metadata().is_ok() {
+                std::fs::remove_file(backup)?;
+            }
+            
+            Ok(())
+        },
+        None if path.symlink_metadata().is_ok() => std::fs::remove_file(path),
+        None => Ok(()),
+    }
+}
+
+/// Runs using a file written by a manifest, and where its original contents are kept.
+struct Users {
+    /// Backup of the original file, or `None` if the file didn't exist.
+    original: Option<Utf8PathBuf>,
+    count: u32,
+}
+impl Users {
+    fn path_for(path: &Utf8Path) -> Utf8PathBuf {
+        let name = path.file_name().unwrap_or("file");
+        
+        path.with_file_name(format!(".{name}.emu-runner-users"))
+    }
+    
+    /// Reads the users of a file, or returns `None` if no run is using it.
+    fn load(path: &Utf8Path) -> std::io::Result<Option<Self>> {
+        let text = match std::fs::read_to_string(Self::path_for(path)) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        
+        let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid users file for {path}"));
+        let text = text.trim_end_matches('\n');
+        let (count, original) = text.split_once('\n').unwrap_or((text, ""));
+        
+        Ok(Some(Self {
+            original: (!original.is_empty()).then(|| original.into()),
+            count: count.parse().map_err(|_| invalid())?,
+        }))
+    }
+    
+    fn save(&self, path: &Utf8Path) -> std::io::Result<()> {
+        let original = self.original.as_ref().map(|backup| backup.as_str()).unwrap_or("");
+        
+        write_atomic(format!("{}\n{original}\n", self.count).as_bytes(), Self::path_for(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::includes::create_temp_dir;
+    
+    #[test]
+    fn record_keeps_original_in_place() {
+        let dir = create_temp_dir("emu-runner-test").unwrap();
+        let path = dir.join("config.ini");
+        std::fs::write(&path, "original").unwrap();
+        
+        let mut manifest = Manifest::new();
+        manifest.record(&path).unwrap();
+        
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "original");
+        let backup = manifest.original(&path).unwrap().to_path_buf();
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "original");
+        
+        manifest.write(b"changed", &path).unwrap();
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "original");
+        
+        manifest.restore().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "original");
+        assert!(!backup.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+    
+    #[test]
+    fn backups_are_unique() {
+        let dir = create_temp_dir("emu-runner-test").unwrap();
+        let path = dir.join("config.ini");
+        std::fs::write(&path, "original").unwrap();
+        
+        let (mut first, mut second) = (Manifest::new(), Manifest::new());
+        first.record(&path).unwrap();
+        let first_backup = first.original(&path).unwrap().to_path_buf();
+        first.restore().unwrap();
+        second.record(&path).unwrap();
+        
+        assert_ne!(second.original(&path), Some(first_backup.as_path()));
+        second.restore().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "original");
+        assert_eq!(dir.read_dir_utf8().unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+    
+    #[test]
+    fn shared_files_are_restored_by_the_last_user() {
+        let dir = create_temp_dir("emu-runner-test").unwrap();
+        let (rom, config) = (dir.join("game.nes"), dir.join("config.ini"));
+        std::fs::write(&config, "ORIGINAL").unwrap();
+        
+        let (mut first, mut second) = (Manifest::new(), Manifest::new());
+        first.write(b"rom", &rom).unwrap();
+        first.write(b"generated", &config).unwrap();
+        second.write(b"rom", &rom).unwrap();
+        second.write(b"generated", &config).unwrap();
+        assert!(matches!(second.entries(), [ManifestEntry::Shared(_), ManifestEntry::Shared(_)]));
+        
+        first.restore().unwrap();
+        assert_eq!(std::fs::read_to_string(&rom).unwrap(), "rom");
+        assert_eq!(std::fs::read_to_string(&config).unwrap(), "generated");
+        
+        second.restore().unwrap();
+        assert!(!rom.exists());
+        assert_eq!(std::fs::read_to_string(&config).unwrap(), "ORIGINAL");
+        assert_eq!(dir.read_dir_utf8().unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+    
+    #[test]
+    fn files_in_use_cant_be_changed() {
+        let dir = create_temp_dir("emu-runner-test").unwrap();
+        let config = dir.join("config.ini");
+        std::fs::write(&config, "ORIGINAL").unwrap();
+        
+        let (mut first, mut second) = (Manifest::new(), Manifest::new());
+        first.write(b"first", &config).unwrap();
+        let err = second.write(b"second", &config).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ResourceBusy);
+        assert_eq!(std::fs::read_to_string(&config).unwrap(), "first");
+        
+        second.restore().unwrap();
+        first.restore().unwrap();
+        assert_eq!(std::fs::read_to_string(&config).unwrap(), "ORIGINAL");
+        assert_eq!(dir.read_dir_utf8().unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+    
+    #[test]
+    fn restore_removes_created_files() {
+        let dir = create_temp_dir("emu-runner-test").unwrap();
+        let path = dir.join("saves/game.sav");
+        
+        let mut manifest = Manifest::new();
+        manifest.create_dir_all(path.parent().unwrap()).unwrap();
+        manifest.write(b"data", &path).unwrap();
+        assert!(path.is_file());
+        
+        manifest.restore().unwrap();
+        assert!(!dir.join("saves").exists());
+        assert!(manifest.is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+use camino::Utf8PathBuf;
+use crate::{EmulatorContext, Error};
+use crate::doctor::Requirement;
+use crate::env::EnvInheritance;
+use crate::limits::ResourceLimits;
+use crate::lock::LockMode;
+
+/// Runs a context inside Linux namespaces using [bubblewrap](https://github.com/containers/bubblewrap) (`bwrap`),
+/// so untrusted movies and Lua scripts can be replayed safely.
+/// 
+/// Inside the namespace, the whole file system is mounted read-only, `/tmp` is a private tmpfs, and only
+/// the working directory (plus any [`writable`](Namespaced::writable) paths) can be written to.
+/// Files in the temporary directory which the emulator is given (e.g. per-run configs) stay visible.
+/// The network, and every other namespace, is unshared, and the emulator is killed if emu-runner exits.
+/// 
+/// **Note:** Only supported on Linux. Combine with [`Sandboxed`](crate::sandbox::Sandboxed) so the writable
+/// working directory is a throwaway copy rather than the emulator's install directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Namespaced<C: EmulatorContext> {
+    pub inner: C,
+    
+    /// Additional paths which can be written to inside the namespace.
+    pub writable: Vec<Utf8PathBuf>,
+    
+    /// Additional paths which are hidden from the emulator, by mounting an empty tmpfs over them (e.g. the user's home).
+    pub hidden: Vec<Utf8PathBuf>,
+    
+    /// Whether the emulator keeps access to the network. Defaults to false.
+    pub network: bool,
+}
+impl<C: EmulatorContext> EmulatorContext for Namespaced<C> {
+    fn cmd_name(&self) -> String {
+        "bwrap".into()
+    }
+    
+    fn args(&self) -> Vec<String> {
+        let working_dir = self.inner.working_dir();
+        let mut args: Vec<String> = vec![
+            "--ro-bind".into(), "/".into(), "/".into(),
+            "--dev".into(), "/dev".into(),
+            "--proc".into(), "/proc".into(),
+            "--tmpfs".into(), "/tmp".into(),
+        ];
+        
+        for path in &self.hidden {
+            args.extend(["--tmpfs".into(), path.to_string()]);
+        }
+        
+        // per-run files (e.g. configs, temporary homes) created by emu-runner are writable, anything else is read-only
+        for path in self.temp_paths() {
+            let bind = match path.file_name() {
+                Some(name) if name.starts_with("emu-runner-") && path.is_dir() => "--bind",
+                _ => "--ro-bind",
+            };
+            args.extend([bind.into(), path.to_string(), path.to_string()]);
+        }
+        
+        // keep access to X servers, including ones started by `Headless`
+        if Utf8PathBuf::from("/tmp/.X11-unix").is_dir() {
+            args.extend(["--ro-bind".into(), "/tmp/.X11-unix".into(), "/tmp/.X11-unix".into()]);
+        }
+        
+        for path in std::iter::once(&working_dir).chain(self.writable.iter()) {
+            args.extend(["--bind".into(), path.to_string(), path.to_string()]);
+        }
+        
+        args.extend(["--unshare-all".into(), "--die-with-parent".into(), "--new-session".into()]);
+        if self.network {
+            args.push("--share-net".into());
+        }
+        args.extend(["--chdir".into(), working_dir.to_string()]);
+        
+        args.push("--".into());
+        args.push(self.inner.cmd_name());
+        args.extend(self.inner.args());
+        
+        args
+    }
+    
+    fn env(&self) -> Vec<(String, String)> {
+        self.inner.env()
+    }
+    
+    fn working_dir(&self) -> Utf8PathBuf {
+        self.inner.working_dir()
+    }
+    
+    fn prepare(&mut self) -> Result<(), Error> {
+        self.inner.prepare()?;
+        
+        // bubblewrap can only bind paths which exist
+        for path in &self.writable {
+            std::fs::create_dir_all(path)?;
+        }
+        
+        Ok(())
+    }
+    
+    fn cleanup(&mut self) -> Result<(), Error> {
+        self.inner.cleanup()
+    }
+    
+    fn set_working_dir(&mut self, working_dir: Utf8PathBuf) -> Result<(), Error> {
+        self.inner.set_working_dir(working_dir)
+    }
+    
+    fn supports_concurrent_instances(&self) -> bool {
+        self.inner.supports_concurrent_instances()
+    }
+    
+    fn lock_mode(&self) -> LockMode {
+        self.inner.lock_mode()
+    }
+    
+    fn env_inheritance(&self) -> EnvInheritance {
+        self.inner.env_inheritance()
+    }
+    
+    fn resource_limits(&self) -> ResourceLimits {
+        self.inner.resource_limits()
+    }
+    
+    fn requirements(&self) -> Vec<Requirement> {
+        let mut requirements = self.inner.requirements();
+        requirements.push(Requirement::Program("bwrap".into()));
+        
+        requirements
+    }
+}
+impl<C: EmulatorContext> Namespaced<C> {
+    /// Wraps a context so it's run inside its own namespaces, without network access.
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            writable: vec![],
+            hidden: vec![],
+            network: false,
+        }
+    }
+    
+    /// Adds a path which can be written to inside the namespace. Missing directories are created during `prepare`.
+    pub fn with_writable<P: Into<Utf8PathBuf>>(mut self, path: P) -> Self {
+        self.writable.push(path.into());
+        self
+    }
+    
+    /// Adds a path which is hidden from the emulator.
+    pub fn with_hidden<P: Into<Utf8PathBuf>>(mut self, path: P) -> Self {
+        self.hidden.push(path.into());
+        self
+    }
+    
+    pub fn with_network(self, network: bool) -> Self {
+        Self {
+            network,
+            ..self
+        }
+    }
+    
+    /// Returns the entries of the system's temporary directory referenced by the inner context's arguments or environment,
+    /// which would otherwise be hidden by the private `/tmp`.
+    fn temp_paths(&self) -> Vec<Utf8PathBuf> {
+        let Ok(temp) = Utf8PathBuf::try_from(std::env::temp_dir()) else { return vec![] };
+        
+        let values = self.inner.args().into_iter().chain(self.inner.env().into_iter().map(|(_, value)| value));
+        let mut paths: Vec<Utf8PathBuf> = vec![];
+        for value in values {
+            // handle `--option=/path` style arguments too
+            let value = value.rsplit_once('=').map(|(_, path)| path.to_string()).unwrap_or(value);
+            let Ok(relative) = Utf8PathBuf::from(value).strip_prefix(&temp).map(|path| path.to_path_buf()) else { continue };
+            let Some(first) = relative.components().next() else { continue };
+            
+            let path = temp.join(first);
+            if path.exists() && !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        
+        paths
+    }
+}
\ No newline at end of file
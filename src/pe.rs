@@ -0,0 +1,117 @@
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+use camino::Utf8Path;
+
+/// Target machine of a Windows executable, as stored in its PE header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PeMachine {
+    X86,
+    X64,
+    Arm64,
+    Other(u16),
+}
+impl PeMachine {
+    pub fn from_raw(machine: u16) -> Self {
+        match machine {
+            0x014c => PeMachine::X86,
+            0x8664 => PeMachine::X64,
+            0xaa64 => PeMachine::Arm64,
+            other => PeMachine::Other(other),
+        }
+    }
+    
+    /// Returns true if the executable requires a 64-bit environment.
+    pub fn is_64bit(&self) -> bool {
+        matches!(self, PeMachine::X64 | PeMachine::Arm64)
+    }
+}
+
+/// Reads the machine type from the PE header of a Windows executable.
+/// 
+/// Only the DOS stub and PE signature are read, not the whole file.
+/// Returns an [`ErrorKind::InvalidData`] error if the file is not a PE executable.
+pub fn read_machine<P: AsRef<Utf8Path>>(path: P) -> std::io::Result<PeMachine> {
+    let mut file = std::fs::File::open(path.as_ref())?;
+    
+    let mut dos = [0u8; 64];
+    file.read_exact(&mut dos)?;
+    if &dos[0..2] != b"MZ" {
+        return Err(Error::new(ErrorKind::InvalidData, "missing MZ signature"));
+    }
+    
+    let pe_offset = u32::from_le_bytes([dos[0x3c], dos[0x3d], dos[0x3e], dos[0x3f]]);
+    file.seek(SeekFrom::Start(pe_offset as u64))?;
+    
+    let mut header = [0u8; 6];
+    file.read_exact(&mut header)?;
+    if &header[0..4] != b"PE\0\0" {
+        return Err(Error::new(ErrorKind::InvalidData, "missing PE signature"));
+    }
+    
+    Ok(PeMachine::from_raw(u16::from_le_bytes([header[4], header[5]])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::includes::create_temp_dir;
+    
+    /// Builds a minimal executable: a DOS header pointing at a PE signature and machine type.
+    fn executable(machine: u16) -> Vec<u8> {
+        let pe_offset = 0x80u32;
+        let mut data = vec![0u8; pe_offset as usize];
+        data[0..2].copy_from_slice(b"MZ");
+        data[0x3c..0x40].copy_from_slice(&pe_offset.to_le_bytes());
+        data.extend_from_slice(b"PE\0\0");
+        data.extend_from_slice(&machine.to_le_bytes());
+        data.extend_from_slice(&[0u8; 18]);
+        
+        data
+    }
+    
+    fn read(data: &[u8]) -> std::io::Result<PeMachine> {
+        let dir = create_temp_dir("emu-runner-test").unwrap();
+        let path = dir.join("test.exe");
+        std::fs::write(&path, data).unwrap();
+        
+        let result = read_machine(&path);
+        std::fs::remove_dir_all(dir).unwrap();
+        
+        result
+    }
+    
+    #[test]
+    fn reads_32bit_header() {
+        let machine = read(&executable(0x014c)).unwrap();
+        
+        assert_eq!(machine, PeMachine::X86);
+        assert!(!machine.is_64bit());
+    }
+    
+    #[test]
+    fn reads_64bit_headers() {
+        assert_eq!(read(&executable(0x8664)).unwrap(), PeMachine::X64);
+        assert_eq!(read(&executable(0xaa64)).unwrap(), PeMachine::Arm64);
+        assert!(PeMachine::X64.is_64bit());
+        assert!(PeMachine::Arm64.is_64bit());
+    }
+    
+    #[test]
+    fn keeps_unknown_machines() {
+        assert_eq!(read(&executable(0x01c4)).unwrap(), PeMachine::Other(0x01c4));
+    }
+    
+    #[test]
+    fn rejects_non_pe_files() {
+        assert_eq!(read(b"#!/bin/sh\necho not an executable\n").unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(read(&[b'#'; 64]).unwrap_err().kind(), ErrorKind::InvalidData);
+        
+        // a DOS executable with no PE header after the stub
+        let mut dos = executable(0x014c);
+        dos[0x80..0x84].copy_from_slice(b"NE\0\0");
+        assert_eq!(read(&dos).unwrap_err().kind(), ErrorKind::InvalidData);
+        
+        let mut truncated = executable(0x014c);
+        truncated.truncate(0x82);
+        assert_eq!(read(&truncated).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,159 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Deserialize;
+use crate::Error;
+use crate::includes::hash_file;
+use crate::manifest::Manifest;
+
+/// How an input file (ROM, movie, Lua script) is made available to an emulator.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Placement {
+    /// The file is left where it is, and passed to the emulator as an absolute path.
+    #[default]
+    Absolute,
+    /// The file is copied into the working directory.
+    Copy,
+    /// A symbolic link to the file is created in the working directory.
+    /// 
+    /// **Note:** Creating symlinks on Windows usually requires elevated privileges.
+    Symlink,
+    /// A hard link to the file is created in the working directory.
+    /// 
+    /// Only works if the file is on the same file system as the working directory.
+    Hardlink,
+    /// The file is copied into the working directory, with the start of its SHA1 prepended to the file name.
+    /// 
+    /// Avoids collisions between different files which share a name.
+    HashedCopy,
+}
+impl Placement {
+    /// Makes the file available to an emulator running in `working_dir`, recording any changes in the manifest.
+    /// 
+    /// Returns the path which should be passed to the emulator. Files placed inside the working directory
+    /// are returned relative to it.
+    pub fn place<P: AsRef<Utf8Path>, Q: AsRef<Utf8Path>>(&self, src: P, working_dir: Q, manifest: &mut Manifest) -> Result<Utf8PathBuf, Error> {
+        let (src, working_dir) = (src.as_ref(), working_dir.as_ref());
+        let src = src.canonicalize_utf8().map_err(|_| Error::AbsolutePathFailed)?;
+        let name = src.file_name().ok_or(Error::AbsolutePathFailed)?;
+        
+        if *self == Placement::Absolute {
+            return Ok(src);
+        }
+        
+        let name = match self {
+            Placement::HashedCopy => format!("{}-{name}", &hash_file(&src)?.to_string()[..16]),
+            _ => name.to_string(),
+        };
+        let dest = working_dir.join(&name);
+        
+        // the file is already in the working directory
+        if dest.canonicalize_utf8().ok().as_ref() == Some(&src) {
+            return Ok(name.into());
+        }
+        
+        match self {
+            Placement::Symlink => manifest.symlink(&src, &dest)?,
+            Placement::Hardlink => manifest.hard_link(&src, &dest)?,
+            _ => manifest.copy(&src, &dest)?,
+        }
+        
+        Ok(name.into())
+    }
+}
+
+/// The [`Placement`] used for each kind of input file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Placements {
+    pub rom: Placement,
+    pub movie: Placement,
+    pub lua: Placement,
+}
+impl Placements {
+    /// Uses the same placement for every kind of file.
+    pub fn all(placement: Placement) -> Self {
+        Self {
+            rom: placement,
+            movie: placement,
+            lua: placement,
+        }
+    }
+    
+    pub fn with_rom(self, rom: Placement) -> Self {
+        Self {
+            rom,
+            ..self
+        }
+    }
+    
+    pub fn with_movie(self, movie: Placement) -> Self {
+        Self {
+            movie,
+            ..self
+        }
+    }
+    
+    pub fn with_lua(self, lua: Placement) -> Self {
+        Self {
+            lua,
+            ..self
+        }
+    }
+    
+    /// Checks that each given input file exists, then places it according to its policy.
+    /// 
+    /// Returns [`Error::MissingRom`], [`Error::MissingMovie`] or [`Error::MissingLua`] for the first file which doesn't exist.
+    /// Files placed before an error are still recorded in the manifest, so they're removed when it's restored.
+    pub fn place_inputs<P: AsRef<Utf8Path>>(&self, rom: Option<&Utf8PathBuf>, movie: Option<&Utf8PathBuf>, lua: Option<&Utf8PathBuf>, working_dir: P, manifest: &mut Manifest) -> Result<PlacedFiles, Error> {
+        let working_dir = working_dir.as_ref();
+        let mut placed = PlacedFiles::default();
+        
+        if let Some(rom) = rom {
+            require_file(rom, Error::MissingRom)?;
+            placed.rom = Some(self.rom.place(rom, working_dir, manifest)?);
+        }
+        if let Some(movie) = movie {
+            require_file(movie, Error::MissingMovie)?;
+            placed.movie = Some(self.movie.place(movie, working_dir, manifest)?);
+        }
+        if let Some(lua) = lua {
+            require_file(lua, Error::MissingLua)?;
+            placed.lua = Some(self.lua.place(lua, working_dir, manifest)?);
+        }
+        
+        Ok(placed)
+    }
+}
+
+/// Paths of the input files after they've been [placed](Placement::place), as passed to the emulator.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PlacedFiles {
+    pub rom: Option<Utf8PathBuf>,
+    pub movie: Option<Utf8PathBuf>,
+    pub lua: Option<Utf8PathBuf>,
+}
+
+/// Returns the error built by `missing` (e.g. [`Error::MissingConfig`]) if the path isn't an existing file.
+pub fn require_file<P: AsRef<Utf8Path>>(path: P, missing: fn(Utf8PathBuf) -> Error) -> Result<(), Error> {
+    let path = path.as_ref();
+    if !path.is_file() {
+        return Err(missing(path.to_path_buf()));
+    }
+    
+    Ok(())
+}
+
+/// Normalizes the path of an input file, so it can be used from any working directory.
+/// 
+/// The path is canonicalized if possible. Otherwise (e.g. if the file doesn't exist yet), it's made absolute
+/// relative to the current directory.
+pub fn normalize_path<P: Into<Utf8PathBuf>>(path: P) -> Utf8PathBuf {
+    let path = path.into();
+    if let Ok(canonical) = path.canonicalize_utf8() {
+        return canonical;
+    }
+    
+    std::path::absolute(&path).ok()
+        .and_then(|absolute| Utf8PathBuf::try_from(absolute).ok())
+        .unwrap_or(path)
+}
\ No newline at end of file
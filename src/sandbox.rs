@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+use camino::{Utf8Path, Utf8PathBuf};
+use crate::{EmulatorContext, Error};
+use crate::doctor::Requirement;
+use crate::env::EnvInheritance;
+use crate::includes::{create_temp_dir, create_temp_dir_in};
+use crate::limits::ResourceLimits;
+use crate::lock::{LockMode, LOCK_FILE_NAME, PREPARE_LOCK_FILE_NAME};
+
+/// File extensions which are hardlinked into a sandbox, rather than copied.
+/// 
+/// These are binaries which emulators only read, so sharing them with the original install is safe.
+pub const DEFAULT_LINK_EXTENSIONS: &[&str] = &["exe", "dll", "so", "dylib", "drv", "sys", "ocx", "pdb"];
+
+/// Runs a context inside a throwaway copy of its working directory, so multiple runs never share mutable files.
+/// 
+/// During `prepare`, the emulator's directory is copied into a new sandbox directory. Large, read-only binaries
+/// (see [`DEFAULT_LINK_EXTENSIONS`]) are hardlinked, falling back to a regular copy if linking isn't possible
+/// (e.g. the sandbox is on another file system). Everything else is copied with [`std::fs::copy`], which
+/// uses reflinks on file systems that support them. [Ignored](Self::ignored) paths are symlinked on Unix, so
+/// sandboxes share them with the original install, and left out entirely elsewhere. The working directory's
+/// lock files are never copied.
+/// 
+/// During `cleanup`, every file the run created or modified is moved into the artifacts directory,
+/// keeping its path relative to the working directory, and the sandbox is deleted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sandboxed<C: EmulatorContext> {
+    pub inner: C,
+    
+    /// Directory the run's artifacts are moved into.
+    pub artifacts_dir: Utf8PathBuf,
+    
+    /// Directory sandboxes are created in. Defaults to the system's temporary directory.
+    /// 
+    /// Hardlinks only work if this is on the same file system as the emulator's directory.
+    pub root: Option<Utf8PathBuf>,
+    
+    /// File extensions which are hardlinked instead of copied.
+    pub link_extensions: Vec<String>,
+    
+    /// Paths, relative to the working directory, which are shared with the sandbox instead of copied,
+    /// and never collected as artifacts (e.g. the Wine prefix).
+    pub ignored: Vec<Utf8PathBuf>,
+    
+    source_dir: Utf8PathBuf,
+    sandbox_dir: Option<Utf8PathBuf>,
+    snapshot: HashMap<Utf8PathBuf, (u64, Option<SystemTime>)>,
+}
+impl<C: EmulatorContext> EmulatorContext for Sandboxed<C> {
+    fn cmd_name(&self) -> String {
+        self.inner.cmd_name()
+    }
+    
+    fn args(&self) -> Vec<String> {
+        self.inner.args()
+    }
+    
+    fn env(&self) -> Vec<(String, String)> {
+        self.inner.env()
+    }
+    
+    fn working_dir(&self) -> Utf8PathBuf {
+        self.inner.working_dir()
+    }
+    
+    fn prepare(&mut self) -> Result<(), Error> {
+        if self.sandbox_dir.is_none() {
+            let dir = match self.root.as_ref() {
+                Some(root) => {
+                    std::fs::create_dir_all(root)?;
+                    create_temp_dir_in(root, "emu-runner-sandbox")?
+                },
+                None => create_temp_dir("emu-runner-sandbox")?,
+            };
+            self.sandbox_dir = Some(dir.clone());
+            
+            self.snapshot.clear();
+            self.populate(&self.source_dir.clone(), &dir)?;
+            self.inner.set_working_dir(dir)?;
+        }
+        
+        self.inner.prepare()
+    }
+    
+    fn cleanup(&mut self) -> Result<(), Error> {
+        let result = self.inner.cleanup();
+        
+        if let Some(dir) = self.sandbox_dir.take() {
+            self.collect(&dir, &dir)?;
+            std::fs::remove_dir_all(&dir)?;
+            self.inner.set_working_dir(self.source_dir.clone())?;
+        }
+        
+        result
+    }
+    
+    fn set_working_dir(&mut self, working_dir: Utf8PathBuf) -> Result<(), Error> {
+        self.source_dir = working_dir.clone();
+        self.inner.set_working_dir(working_dir)
+    }
+    
+    fn supports_concurrent_instances(&self) -> bool {
+        true
+    }
+    
+    /// Sandboxes only read from the emulator's directory while running, so it's locked [`Shared`](LockMode::Shared)
+    /// unless the inner context is [`Unlocked`](LockMode::Unlocked). Any number of sandboxed runs may then use the
+    /// directory at once, while runs which need it exclusively wait for them to finish.
+    fn lock_mode(&self) -> LockMode {
+        match self.inner.lock_mode() {
+            LockMode::Unlocked => LockMode::Unlocked,
+            _ => LockMode::Shared,
+        }
+    }
+    
+    fn env_inheritance(&self) -> EnvInheritance {
+        self.inner.env_inheritance()
+    }
+    
+    fn resource_limits(&self) -> ResourceLimits {
+        self.inner.resource_limits()
+    }
+    
+    fn requirements(&self) -> Vec<Requirement> {
+        self.inner.requirements()
+    }
+}
+impl<C: EmulatorContext> Sandboxed<C> {
+    /// Wraps a context so each run happens in a throwaway copy of its working directory.
+    pub fn new<P: Into<Utf8PathBuf>>(inner: C, artifacts_dir: P) -> Self {
+        let source_dir = inner.working_dir();
+        
+        Self {
+            inner,
+            artifacts_dir: artifacts_dir.into(),
+            root: None,
+            link_extensions: DEFAULT_LINK_EXTENSIONS.iter().map(|ext| ext.to_string()).collect(),
+            ignored: vec![".wine".into(), ".wine64".into()],
+            source_dir,
+            sandbox_dir: None,
+            snapshot: HashMap::new(),
+        }
+    }
+    
+    pub fn with_root<P: Into<Utf8PathBuf>>(self, root: P) -> Self {
+        Self {
+            root: Some(root.into()),
+            ..self
+        }
+    }
+    
+    pub fn with_link_extensions(self, link_extensions: Vec<String>) -> Self {
+        Self {
+            link_extensions,
+            ..self
+        }
+    }
+    
+    /// Adds a path, relative to the working directory, which is shared with the sandbox and never collected as an artifact.
+    pub fn with_ignored<P: Into<Utf8PathBuf>>(mut self, path: P) -> Self {
+        self.ignored.push(path.into());
+        self
+    }
+    
+    /// Returns the sandbox directory of the current run, if the context has been prepared.
+    pub fn sandbox_dir(&self) -> Option<&Utf8Path> {
+        self.sandbox_dir.as_deref()
+    }
+    
+    /// Copies or links the contents of `src` into `dest`, recording the state of every file.
+    fn populate(&mut self, src: &Utf8Path, dest: &Utf8Path) -> Result<(), Error> {
+        std::fs::create_dir_all(dest)?;
+        
+        for entry in src.read_dir_utf8()? {
+            let entry = entry?;
+            let target = dest.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            
+            // don't copy the sandbox into itself, if it was placed inside the emulator's directory
+            if Some(entry.path()) == self.root.as_deref() {
+                continue;
+            }
+            
+            let relative = entry.path().strip_prefix(&self.source_dir).unwrap_or(entry.path());
+            if relative == LOCK_FILE_NAME || relative == PREPARE_LOCK_FILE_NAME {
+                continue;
+            }
+            if self.is_ignored(relative) {
+                #[cfg(target_family = "unix")]
+                std::os::unix::fs::symlink(entry.path(), &target)?;
+                
+                continue;
+            }
+            
+            if file_type.is_dir() {
+                self.populate(entry.path(), &target)?;
+            } else if file_type.is_symlink() {
+                #[cfg(target_family = "unix")]
+                std::os::unix::fs::symlink(entry.path().read_link_utf8()?, &target)?;
+                
+                #[cfg(target_family = "windows")]
+                std::fs::copy(entry.path(), &target).map(|_| ())?;
+            } else {
+                let link = entry.path().extension()
+                    .map(|ext| self.link_extensions.iter().any(|other| other.eq_ignore_ascii_case(ext)))
+                    .unwrap_or(false);
+                
+                if !link || std::fs::hard_link(entry.path(), &target).is_err() {
+                    std::fs::copy(entry.path(), &target)?;
+                }
+                
+                let meta = target.metadata()?;
+                self.snapshot.insert(target, (meta.len(), meta.modified().ok()));
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Returns whether a path, relative to the working directory, is one of the [ignored](Self::ignored) paths.
+    fn is_ignored(&self, relative: &Utf8Path) -> bool {
+        self.ignored.iter().any(|ignored| relative.starts_with(ignored))
+    }
+    
+    /// Moves every file in `dir` which was created or modified since the sandbox was populated into the artifacts directory.
+    fn collect(&self, sandbox: &Utf8Path, dir: &Utf8Path) -> Result<(), Error> {
+        for entry in dir.read_dir_utf8()? {
+            let entry = entry?;
+            let relative = entry.path().strip_prefix(sandbox).unwrap_or(entry.path());
+            if relative == LOCK_FILE_NAME || relative == PREPARE_LOCK_FILE_NAME || self.is_ignored(relative) {
+                continue;
+            }
+            
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                self.collect(sandbox, entry.path())?;
+                continue;
+            }
+            if !file_type.is_file() {
+                continue;
+            }
+            
+            let meta = entry.metadata()?;
+            let unchanged = self.snapshot.get(entry.path()) == Some(&(meta.len(), meta.modified().ok()));
+            if unchanged {
+                continue;
+            }
+            
+            let dest = self.artifacts_dir.join(relative);
+            if let Some(parent) = dest.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            if std::fs::rename(entry.path(), &dest).is_err() {
+                std::fs::copy(entry.path(), &dest)?;
+            }
+        }
+        
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,496 @@
+use std::process::{Command, Stdio};
+use camino::{Utf8Path, Utf8PathBuf};
+use crate::Error;
+use crate::doctor::find_program;
+use crate::includes::copy_dir_all;
+use crate::pe::read_machine;
+
+/// Architecture of a Wine prefix.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WineArch {
+    Win32,
+    Win64,
+}
+impl WineArch {
+    /// Determines the architecture needed by an executable by reading its PE header.
+    /// 
+    /// Falls back to [`WineArch::from_executable_name`] if the header can't be read.
+    pub fn from_executable<P: AsRef<Utf8Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        
+        match read_machine(path) {
+            Ok(machine) if machine.is_64bit() => WineArch::Win64,
+            Ok(_) => WineArch::Win32,
+            Err(_) => WineArch::from_executable_name(path.file_name().unwrap_or(path.as_str())),
+        }
+    }
+    
+    /// Guesses the architecture needed by an executable from its file name (e.g. `fceux64.exe`).
+    pub fn from_executable_name(name: &str) -> Self {
+        let stem = name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(name);
+        
+        if stem.ends_with("64") {
+            WineArch::Win64
+        } else {
+            WineArch::Win32
+        }
+    }
+    
+    /// Value used for the `WINEARCH` environment variable.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WineArch::Win32 => "win32",
+            WineArch::Win64 => "win64",
+        }
+    }
+}
+
+/// Options used when a Windows executable is launched through Wine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WineOptions {
+    /// Drive letters mapped to host directories, in addition to Wine's default `Z:` mapping of `/`.
+    pub drives: Vec<(char, Utf8PathBuf)>,
+    
+    /// Prefix directory. If unset, contexts use `<working_dir>/.wine/` for 32-bit executables and
+    /// `<working_dir>/.wine64/` for 64-bit executables, so builds of both architectures never share a prefix.
+    pub prefix: Option<Utf8PathBuf>,
+    
+    /// Prefix architecture. If unset, it is read from the executable's PE header.
+    pub arch: Option<WineArch>,
+    
+    /// Wine loader command (e.g. `wine` or `wine64`). If unset, it is picked based on the architecture.
+    pub loader: Option<String>,
+    
+    /// Value of `WINEDEBUG`. Inherited from the parent environment if unset.
+    pub debug: Option<String>,
+    
+    /// DLL overrides joined into `WINEDLLOVERRIDES`, as `(dlls, mode)` pairs (e.g. `("dsound", "n,b")`).
+    pub dll_overrides: Vec<(String, String)>,
+    
+    /// Existing prefix copied into place when the prefix has not been initialized yet.
+    pub template: Option<Utf8PathBuf>,
+    
+    /// Whether a missing prefix is initialized during `prepare`, rather than on first launch. Defaults to true.
+    pub initialize: bool,
+}
+impl Default for WineOptions {
+    fn default() -> Self {
+        Self {
+            drives: vec![],
+            prefix: None,
+            arch: None,
+            loader: None,
+            debug: None,
+            dll_overrides: vec![],
+            template: None,
+            initialize: true,
+        }
+    }
+}
+impl WineOptions {
+    pub fn with_prefix<P: Into<Utf8PathBuf>>(self, prefix: P) -> Self {
+        Self {
+            prefix: Some(prefix.into()),
+            ..self
+        }
+    }
+    
+    pub fn with_arch(self, arch: WineArch) -> Self {
+        Self {
+            arch: Some(arch),
+            ..self
+        }
+    }
+    
+    pub fn with_loader<S: Into<String>>(self, loader: S) -> Self {
+        Self {
+            loader: Some(loader.into()),
+            ..self
+        }
+    }
+    
+    pub fn with_debug<S: Into<String>>(self, debug: S) -> Self {
+        Self {
+            debug: Some(debug.into()),
+            ..self
+        }
+    }
+    
+    pub fn with_dll_override<D: Into<String>, M: Into<String>>(mut self, dlls: D, mode: M) -> Self {
+        self.dll_overrides.push((dlls.into(), mode.into()));
+        self
+    }
+    
+    pub fn with_template<P: Into<Utf8PathBuf>>(self, template: P) -> Self {
+        Self {
+            template: Some(template.into()),
+            ..self
+        }
+    }
+    
+    pub fn with_initialize(self, initialize: bool) -> Self {
+        Self {
+            initialize,
+            ..self
+        }
+    }
+    
+    /// Returns the architecture used for `executable`, unless overridden.
+    pub fn arch_for<P: AsRef<Utf8Path>>(&self, executable: P) -> WineArch {
+        self.arch.unwrap_or_else(|| WineArch::from_executable(executable))
+    }
+    
+    /// Returns the loader command used to launch `executable`, unless overridden.
+    /// 
+    /// 64-bit executables use `wine64` when it is installed. Newer Wine releases only provide
+    /// `wine`, which handles both architectures.
+    pub fn loader_for<P: AsRef<Utf8Path>>(&self, executable: P) -> String {
+        if let Some(loader) = self.loader.as_ref() {
+            return loader.clone();
+        }
+        
+        match self.arch_for(executable) {
+            WineArch::Win64 if find_program("wine64").is_some() => "wine64".into(),
+            _ => "wine".into(),
+        }
+    }
+    
+    /// Builds the prefix used to run `executable` from `working_dir`.
+    pub fn prefix_for<P: AsRef<Utf8Path>, E: AsRef<Utf8Path>>(&self, working_dir: P, executable: E) -> WinePrefix {
+        let executable = executable.as_ref();
+        let arch = self.arch_for(executable);
+        let path = self.prefix.clone().unwrap_or_else(|| match arch {
+            WineArch::Win32 => working_dir.as_ref().join(".wine/"),
+            WineArch::Win64 => working_dir.as_ref().join(".wine64/"),
+        });
+        
+        WinePrefix {
+            path,
+            arch,
+            loader: self.loader_for(executable),
+            debug: self.debug.clone(),
+            dll_overrides: self.dll_overrides.clone(),
+        }
+    }
+    
+    /// Makes sure the prefix exists, copying the template or initializing it as configured, then maps drives.
+    /// 
+    /// Returns [`Error::WinePrefixFailed`] if an existing 32-bit prefix would be used for a 64-bit executable,
+    /// since `WINEARCH` only takes effect when a prefix is created.
+    pub fn prepare_prefix(&self, prefix: &WinePrefix) -> Result<(), Error> {
+        if !prefix.is_initialized() {
+            if let Some(template) = self.template.as_ref() {
+                prefix.init_from_template(template)?;
+            } else if self.initialize {
+                prefix.init()?;
+            }
+        }
+        if prefix.arch == WineArch::Win64 && prefix.installed_arch() == Some(WineArch::Win32) {
+            return Err(Error::WinePrefixFailed(prefix.path.clone()));
+        }
+        
+        #[cfg(target_family = "unix")]
+        self.map_drives(&prefix.path)?;
+        
+        Ok(())
+    }
+    
+    /// Maps a drive letter to a host directory.
+    /// 
+    /// Paths inside `dir` will be translated onto this drive instead of `Z:`.
+    pub fn with_drive<P: Into<Utf8PathBuf>>(mut self, letter: char, dir: P) -> Self {
+        let dir = dir.into();
+        let letter = letter.to_ascii_uppercase();
+        
+        self.drives.retain(|(other, _)| *other != letter);
+        self.drives.push((letter, dir.canonicalize_utf8().unwrap_or(dir)));
+        
+        self
+    }
+    
+    /// Translates a host path into the Windows path Wine will resolve to the same file.
+    /// 
+    /// Absolute paths are placed on the mapped drive with the longest matching directory, falling back to `Z:`.
+    /// Relative paths only have their separators converted. Spaces and non-ASCII names are kept as-is;
+    /// each path is passed as its own argument, so no quoting is necessary.
+    pub fn to_windows_path<P: AsRef<Utf8Path>>(&self, path: P) -> String {
+        let path = path.as_ref();
+        if !path.is_absolute() {
+            return join_windows(None, path.components().map(|comp| comp.as_str()));
+        }
+        
+        let mapped = self.drives.iter()
+            .filter_map(|(letter, dir)| path.strip_prefix(dir).ok().map(|rest| (*letter, dir, rest)))
+            .max_by_key(|(_, dir, _)| dir.components().count());
+        
+        match mapped {
+            Some((letter, _, rest)) => join_windows(Some(letter), rest.components().map(|comp| comp.as_str())),
+            None => join_windows(Some('Z'), path.components().skip(1).map(|comp| comp.as_str())),
+        }
+    }
+    
+    /// Creates the `dosdevices` links for every mapped drive inside the given Wine prefix.
+    #[cfg(target_family = "unix")]
+    pub fn map_drives<P: AsRef<Utf8Path>>(&self, prefix: P) -> std::io::Result<()> {
+        if self.drives.is_empty() {
+            return Ok(());
+        }
+        
+        let devices = prefix.as_ref().join("dosdevices");
+        std::fs::create_dir_all(&devices)?;
+        
+        for (letter, dir) in &self.drives {
+            let link = devices.join(format!("{}:", letter.to_ascii_lowercase()));
+            if link.symlink_metadata().is_ok() {
+                if link.read_link_utf8().ok().as_ref() == Some(dir) {
+                    continue;
+                }
+                std::fs::remove_file(&link)?;
+            }
+            
+            std::os::unix::fs::symlink(dir, link)?;
+        }
+        
+        Ok(())
+    }
+}
+
+fn join_windows<'a, I: Iterator<Item = &'a str>>(drive: Option<char>, parts: I) -> String {
+    let path = parts.collect::<Vec<_>>().join("\\");
+    
+    match drive {
+        Some(letter) => format!("{letter}:\\{path}"),
+        None => path,
+    }
+}
+
+/// A Wine prefix which can be created, cloned, and torn down without user interaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WinePrefix {
+    pub path: Utf8PathBuf,
+    pub arch: WineArch,
+    
+    /// Loader command used to run programs inside the prefix.
+    pub loader: String,
+    
+    /// Value of `WINEDEBUG`, if any.
+    pub debug: Option<String>,
+    
+    /// DLL overrides joined into `WINEDLLOVERRIDES`.
+    pub dll_overrides: Vec<(String, String)>,
+}
+impl WinePrefix {
+    pub fn new<P: Into<Utf8PathBuf>>(path: P, arch: WineArch) -> Self {
+        Self {
+            path: path.into(),
+            arch,
+            loader: "wine".into(),
+            debug: None,
+            dll_overrides: vec![],
+        }
+    }
+    
+    /// Returns the environment variables needed to run programs inside this prefix.
+    /// 
+    /// `WINEARCH` is only used while creating the prefix, so it is not included.
+    pub fn env(&self) -> Vec<(String, String)> {
+        let mut vars = vec![("WINEPREFIX".to_string(), self.path.to_string())];
+        
+        if let Some(debug) = self.debug.as_ref() {
+            vars.push(("WINEDEBUG".into(), debug.clone()));
+        }
+        if !self.dll_overrides.is_empty() {
+            vars.push(("WINEDLLOVERRIDES".into(), join_overrides(&self.dll_overrides)));
+        }
+        
+        vars
+    }
+    
+    /// Returns true if Wine has already set up this prefix.
+    pub fn is_initialized(&self) -> bool {
+        self.path.join("system.reg").is_file()
+    }
+    
+    /// Returns the architecture the prefix was created with, read from the `#arch=` line of `system.reg`.
+    /// 
+    /// Returns `None` if the prefix isn't initialized, or the architecture isn't recorded.
+    pub fn installed_arch(&self) -> Option<WineArch> {
+        let registry = std::fs::read(self.path.join("system.reg")).ok()?;
+        
+        String::from_utf8_lossy(&registry).lines()
+            .find_map(|line| line.trim().strip_prefix("#arch="))
+            .and_then(|arch| match arch.trim() {
+                "win32" => Some(WineArch::Win32),
+                "win64" => Some(WineArch::Win64),
+                _ => None,
+            })
+    }
+    
+    /// Creates the prefix with `wineboot`, if it isn't initialized already.
+    /// 
+    /// Mono and Gecko installation is skipped so no dialogs are shown.
+    pub fn init(&self) -> Result<(), Error> {
+        if self.is_initialized() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.path)?;
+        
+        let mut overrides = self.dll_overrides.clone();
+        overrides.push(("mscoree,mshtml".into(), "".into()));
+        
+        self.command(&self.loader)
+            .args(["wineboot", "--init"])
+            .env("WINEARCH", self.arch.as_str())
+            .env("WINEDLLOVERRIDES", join_overrides(&overrides))
+            .status()
+            .map_err(|_| Error::MissingWine(self.loader.clone().into()))
+            .and_then(|status| self.check(status.success()))?;
+        
+        self.wait()
+    }
+    
+    /// Creates the prefix by copying an existing, already initialized, prefix.
+    pub fn init_from_template<P: AsRef<Utf8Path>>(&self, template: P) -> Result<(), Error> {
+        let template = template.as_ref();
+        if !template.join("system.reg").is_file() {
+            return Err(Error::WinePrefixFailed(template.to_path_buf()));
+        }
+        
+        copy_dir_all(template, &self.path)?;
+        
+        Ok(())
+    }
+    
+    /// Kills every process running in this prefix with `wineserver -k`.
+    pub fn kill(&self) -> Result<(), Error> {
+        // wineserver reports a failure if no server was running, which is fine here
+        self.wineserver("-k").map(|_| ())
+    }
+    
+    /// Kills any remaining processes and deletes the prefix directory.
+    pub fn remove(self) -> Result<(), Error> {
+        if self.path.is_dir() {
+            self.kill()?;
+            std::fs::remove_dir_all(&self.path)?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Blocks until every process in this prefix has exited.
+    pub fn wait(&self) -> Result<(), Error> {
+        let success = self.wineserver("--wait")?;
+        
+        self.check(success)
+    }
+    
+    fn wineserver(&self, arg: &str) -> Result<bool, Error> {
+        let status = self.command("wineserver")
+            .arg(arg)
+            .status()
+            .map_err(|_| Error::MissingWine("wineserver".into()))?;
+        
+        Ok(status.success())
+    }
+    
+    fn command(&self, program: &str) -> Command {
+        let mut cmd = Command::new(program);
+        cmd.envs(self.env())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        
+        cmd
+    }
+    
+    fn check(&self, success: bool) -> Result<(), Error> {
+        if success {
+            Ok(())
+        } else {
+            Err(Error::WinePrefixFailed(self.path.clone()))
+        }
+    }
+}
+
+fn join_overrides(overrides: &[(String, String)]) -> String {
+    overrides.iter()
+        .map(|(dlls, mode)| format!("{dlls}={mode}"))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+#[cfg(test)]
+#[cfg(target_family = "unix")]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn windows_path_defaults_to_z_drive() {
+        let wine = WineOptions::default();
+        
+        assert_eq!(wine.to_windows_path("/home/user/roms/game.nes"), "Z:\\home\\user\\roms\\game.nes");
+        assert_eq!(wine.to_windows_path("/"), "Z:\\");
+    }
+    
+    #[test]
+    fn windows_path_uses_longest_mapped_drive() {
+        let wine = WineOptions::default()
+            .with_drive('d', "/nonexistent/games")
+            .with_drive('e', "/nonexistent/games/nes");
+        
+        assert_eq!(wine.to_windows_path("/nonexistent/games/snes/game.sfc"), "D:\\snes\\game.sfc");
+        assert_eq!(wine.to_windows_path("/nonexistent/games/nes/game.nes"), "E:\\game.nes");
+        assert_eq!(wine.to_windows_path("/nonexistent/gamesx/game.nes"), "Z:\\nonexistent\\gamesx\\game.nes");
+    }
+    
+    #[test]
+    fn windows_path_remaps_existing_drive_letter() {
+        let wine = WineOptions::default()
+            .with_drive('D', "/nonexistent/old")
+            .with_drive('d', "/nonexistent/new");
+        
+        assert_eq!(wine.to_windows_path("/nonexistent/new/game.nes"), "D:\\game.nes");
+        assert_eq!(wine.to_windows_path("/nonexistent/old/game.nes"), "Z:\\nonexistent\\old\\game.nes");
+    }
+    
+    #[test]
+    fn windows_path_keeps_spaces_and_non_ascii() {
+        let wine = WineOptions::default().with_drive('R', "/nonexistent/my roms");
+        
+        assert_eq!(wine.to_windows_path("/nonexistent/my roms/Super Mario Bros.nes"), "R:\\Super Mario Bros.nes");
+        assert_eq!(wine.to_windows_path("/tmp/ドンキーコング/ゲーム.nes"), "Z:\\tmp\\ドンキーコング\\ゲーム.nes");
+    }
+    
+    #[test]
+    fn windows_path_converts_relative_separators() {
+        let wine = WineOptions::default().with_drive('D', "/nonexistent");
+        
+        assert_eq!(wine.to_windows_path("roms/game.nes"), "roms\\game.nes");
+        assert_eq!(wine.to_windows_path("../movies/run 1.fm2"), "..\\movies\\run 1.fm2");
+        assert_eq!(wine.to_windows_path("game.nes"), "game.nes");
+    }
+    
+    #[test]
+    fn default_prefix_depends_on_arch() {
+        let win32 = WineOptions::default().with_arch(WineArch::Win32);
+        let win64 = WineOptions::default().with_arch(WineArch::Win64);
+        
+        assert_eq!(win32.prefix_for("/emu", "/emu/app.exe").path, "/emu/.wine/");
+        assert_eq!(win64.prefix_for("/emu", "/emu/app.exe").path, "/emu/.wine64/");
+    }
+    
+    #[test]
+    fn installed_arch_reads_system_registry() {
+        let dir = crate::includes::create_temp_dir("emu-runner-test").unwrap();
+        let prefix = WinePrefix::new(&dir, WineArch::Win64);
+        assert_eq!(prefix.installed_arch(), None);
+        
+        std::fs::write(dir.join("system.reg"), "WINE REGISTRY Version 2\n;; All keys relative to \\\\Machine\n\n#arch=win32\n").unwrap();
+        assert_eq!(prefix.installed_arch(), Some(WineArch::Win32));
+        
+        std::fs::write(dir.join("system.reg"), "WINE REGISTRY Version 2\r\n#arch=win64\r\n").unwrap();
+        assert_eq!(prefix.installed_arch(), Some(WineArch::Win64));
+        
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+use camino::Utf8PathBuf;
+use crate::{EmulatorContext, Error};
+use crate::doctor::Requirement;
+use crate::env::EnvInheritance;
+use crate::limits::ResourceLimits;
+use crate::lock::LockMode;
+
+/// Runs a context under a helper command, such as `nice`, `taskset`, `timeout` or `gamescope`.
+/// 
+/// The helper and its arguments are placed before the emulator's command, which keeps its own arguments
+/// and working directory. Wrappers can be nested; the outermost wrapper runs first.
+/// 
+/// ```no_run
+/// # use emu_runner::contexts::FceuxContext;
+/// # use emu_runner::wrapper::Wrapped;
+/// let ctx = FceuxContext::new("fceux/").unwrap();
+/// 
+/// // timeout 600 nice -n 10 ./fceux ...
+/// let ctx = Wrapped::new(ctx, "nice").with_args(["-n", "10"]);
+/// let ctx = Wrapped::new(ctx, "timeout").with_arg("600");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wrapped<C: EmulatorContext> {
+    pub inner: C,
+    
+    /// Helper program to run.
+    pub program: String,
+    
+    /// Arguments passed to the helper, before the emulator's command.
+    pub args: Vec<String>,
+    
+    /// Environment variables added to the emulator's environment, overriding any with the same name.
+    pub env: Vec<(String, String)>,
+}
+impl<C: EmulatorContext> EmulatorContext for Wrapped<C> {
+    fn cmd_name(&self) -> String {
+        self.program.clone()
+    }
+    
+    fn args(&self) -> Vec<String> {
+        let mut args = self.args.clone();
+        args.push(self.inner.cmd_name());
+        args.extend(self.inner.args());
+        
+        args
+    }
+    
+    fn env(&self) -> Vec<(String, String)> {
+        let mut vars = self.inner.env();
+        vars.retain(|(key, _)| !self.env.iter().any(|(other, _)| other == key));
+        vars.extend(self.env.iter().cloned());
+        
+        vars
+    }
+    
+    fn working_dir(&self) -> Utf8PathBuf {
+        self.inner.working_dir()
+    }
+    
+    fn prepare(&mut self) -> Result<(), Error> {
+        self.inner.prepare()
+    }
+    
+    fn cleanup(&mut self) -> Result<(), Error> {
+        self.inner.cleanup()
+    }
+    
+    fn set_working_dir(&mut self, working_dir: Utf8PathBuf) -> Result<(), Error> {
+        self.inner.set_working_dir(working_dir)
+    }
+    
+    fn supports_concurrent_instances(&self) -> bool {
+        self.inner.supports_concurrent_instances()
+    }
+    
+    fn lock_mode(&self) -> LockMode {
+        self.inner.lock_mode()
+    }
+    
+    fn env_inheritance(&self) -> EnvInheritance {
+        self.inner.env_inheritance()
+    }
+    
+    fn resource_limits(&self) -> ResourceLimits {
+        self.inner.resource_limits()
+    }
+    
+    /// The helper program must be on `PATH`, in addition to the inner context's requirements.
+    fn requirements(&self) -> Vec<Requirement> {
+        let mut requirements = self.inner.requirements();
+        requirements.push(Requirement::Program(self.program.clone()));
+        
+        requirements
+    }
+}
+impl<C: EmulatorContext> Wrapped<C> {
+    /// Wraps a context so it's run by the given helper program.
+    pub fn new<S: Into<String>>(inner: C, program: S) -> Self {
+        Self {
+            inner,
+            program: program.into(),
+            args: vec![],
+            env: vec![],
+        }
+    }
+    
+    /// Adds an argument passed to the helper program.
+    pub fn with_arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+    
+    /// Adds several arguments passed to the helper program.
+    pub fn with_args<I: IntoIterator<Item = S>, S: Into<String>>(mut self, args: I) -> Self {
+        self.args.extend(args.into_iter().map(|arg| arg.into()));
+        self
+    }
+    
+    /// Adds an environment variable, overriding the inner context's value if it sets the same one.
+    pub fn with_env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+}
\ No newline at end of file